@@ -1,17 +1,36 @@
-use once_cell::sync::Lazy;
-use std::path::PathBuf;
+use once_cell::sync::{Lazy, OnceCell};
+use std::path::{Path, PathBuf};
 
 const TOP_DIR_NAME: &str = "AutoPkg";
+/// Folder in the home folder used when there's no config folder
+const HIDDEN_DIR_NAME: &str = ".autopkg";
 const RECIPES_DIR_NAME: &str = "Recipes";
 const RECIPE_REPO_DIR_NAME: &str = "RecipeRepos";
 const RECIPE_OVERRIDES_NAME: &str = "RecipeOverrides";
 const CACHE_DIR_NAME: &str = "Cache";
 const RECIPE_MAP_FILENAME: &str = "recipe_map.json";
 const GH_TOKEN_FILENAME: &str = "gh_token";
+/// Name of the log file written to the cache dir with --log-file
+pub const LOG_FILENAME: &str = "autopkg.log";
 pub const REPO_LIST_FILENAME: &str = "repo_list.json";
 const PREFERENCES_FILENAME: &str = "autopkg_prefs.json";
+/// Name of a preferences file that applies to the folder it's in, and every
+/// folder below it
+pub const PROJECT_PREFERENCES_FILENAME: &str = ".autopkg_prefs.json";
 const REPO_MAP_FILENAME: &str = "repo_map.json";
 pub const GITHUB_ORG_NAME: &str = "autopkg";
+/// File extensions of recipes and overrides, longest first so a
+/// `.recipe.yaml` file isn't mistaken for a plain `.recipe`
+pub const RECIPE_EXTENSIONS: [&str; 3] = [".recipe.yaml", ".recipe.plist", ".recipe"];
+/// Name of the file listing glob patterns to skip when searching the folder
+/// it's in for recipes
+pub const AUTOPKGIGNORE_FILENAME: &str = ".autopkgignore";
+/// Version compared against a recipe's MinimumVersion
+pub const AUTOPKG_VERSION: &str = "4.0";
+/// Preferences domain of Python AutoPkg, which is also its plist's name on macOS
+const PYTHON_BUNDLE_ID: &str = "com.github.autopkg";
+/// Folder Python AutoPkg keeps its preferences in on other platforms
+const PYTHON_APP_NAME: &str = "Autopkg";
 
 // Why are we using Lazy statics here instead of just constant strings?
 //
@@ -22,115 +41,172 @@ pub const GITHUB_ORG_NAME: &str = "autopkg";
 // So instead, we're creating Lazy static PathBufs that are constructed at
 // runtime.
 
+/// The system-wide AutoPkg folder, shared by every user
 pub static DEFAULT_LIBRARY_DIR: Lazy<PathBuf> = Lazy::new(|| {
-    [dirs::config_dir().unwrap(), PathBuf::from(TOP_DIR_NAME)]
-        .iter()
-        .collect()
+    if cfg!(target_os = "macos") {
+        PathBuf::from("/Library/Application Support").join(TOP_DIR_NAME)
+    } else if cfg!(windows) {
+        std::env::var_os("PROGRAMDATA")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(r"C:\ProgramData"))
+            .join(TOP_DIR_NAME)
+    } else {
+        PathBuf::from("/etc/autopkg")
+    }
 });
 // pub const DEFAULT_LIBRARY_DIR: &str = "%PROGRAMDATA%/AutoPkg";
 // pub const DEFAULT_LIBRARY_DIR: &str = "/Library/Application Support/AutoPkg";
 
-pub static USER_LIBRARY_DIR: Lazy<PathBuf> = Lazy::new(|| {
-    [dirs::config_dir().unwrap(), PathBuf::from(TOP_DIR_NAME)]
-        .iter()
-        .collect()
-});
+/// Name of the file that puts AutoPkg in portable mode when it's next to the
+/// binary
+pub const PORTABLE_MARKER_FILENAME: &str = "autopkg.portable";
+
+static PORTABLE_ROOT: OnceCell<Option<PathBuf>> = OnceCell::new();
+
+/// Keep every AutoPkg folder and file in one folder, instead of the user's
+/// config folder. This has to happen before any of the paths below are used,
+/// and returns false if it's too late.
+#[must_use]
+pub fn set_portable_root(root: PathBuf) -> bool {
+    PORTABLE_ROOT.set(Some(root)).is_ok()
+}
+
+/// The folder AutoPkg keeps everything in when it's in portable mode. That's
+/// the one given to `set_portable_root()`, or else the folder the binary is in
+/// if there's a portable marker file next to it.
+pub fn portable_root() -> Option<&'static Path> {
+    PORTABLE_ROOT
+        .get_or_init(|| {
+            let exe_dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
+            exe_dir
+                .join(PORTABLE_MARKER_FILENAME)
+                .is_file()
+                .then_some(exe_dir)
+        })
+        .as_deref()
+}
+
+/// Pick the user's AutoPkg folder: the portable root if there is one, then
+/// the AutoPkg folder in the config folder, then a hidden folder in the home
+/// folder, and the temp folder as a last resort
+fn user_library_dir(
+    portable_root: Option<&Path>,
+    config_dir: Option<PathBuf>,
+    home_dir: Option<PathBuf>,
+) -> PathBuf {
+    if let Some(root) = portable_root {
+        return root.to_path_buf();
+    }
+    if let Some(config_dir) = config_dir {
+        return config_dir.join(TOP_DIR_NAME);
+    }
+    match home_dir {
+        Some(home_dir) => home_dir.join(HIDDEN_DIR_NAME),
+        None => std::env::temp_dir().join(TOP_DIR_NAME),
+    }
+}
+
+pub static USER_LIBRARY_DIR: Lazy<PathBuf> =
+    Lazy::new(|| user_library_dir(portable_root(), dirs::config_dir(), dirs::home_dir()));
 // pub const USER_LIBRARY_DIR: &str = "%APPDATA%/AutoPkg";
 // pub const USER_LIBRARY_DIR: &str = "~/Library/Application Support/AutoPkg";
 
-pub static USER_RECIPES_DIR: Lazy<PathBuf> = Lazy::new(|| {
-    [
-        dirs::config_dir().unwrap(),
-        PathBuf::from(TOP_DIR_NAME),
-        PathBuf::from(RECIPES_DIR_NAME),
-    ]
-    .iter()
-    .collect()
-});
+pub static USER_RECIPES_DIR: Lazy<PathBuf> = Lazy::new(|| USER_LIBRARY_DIR.join(RECIPES_DIR_NAME));
 // pub const USER_RECIPES_DIR: &str = "%APPDATA%/AutoPkg/Recipes";
 // pub const USER_RECIPES_DIR: &str = "~/Library/Application Support/AutoPkg/Recipes";
 
-pub static DEFAULT_CACHE_DIR: Lazy<PathBuf> = Lazy::new(|| {
-    [
-        dirs::config_dir().unwrap(),
-        PathBuf::from(TOP_DIR_NAME),
-        PathBuf::from(CACHE_DIR_NAME),
-    ]
-    .iter()
-    .collect()
-});
+pub static DEFAULT_CACHE_DIR: Lazy<PathBuf> = Lazy::new(|| USER_LIBRARY_DIR.join(CACHE_DIR_NAME));
 // pub const DEFAULT_CACHE_DIR: &str = "%APPDATA%/AutoPkg/Cache";
 // pub const DEFAULT_CACHE_DIR: &str = "~/Library/Application Support/AutoPkg/Cache";
 
-pub static DEFAULT_RECIPE_REPOS_DIR: Lazy<PathBuf> = Lazy::new(|| {
-    [
-        dirs::config_dir().unwrap(),
-        PathBuf::from(TOP_DIR_NAME),
-        PathBuf::from(RECIPE_REPO_DIR_NAME),
-    ]
-    .iter()
-    .collect()
-});
+pub static DEFAULT_RECIPE_REPOS_DIR: Lazy<PathBuf> =
+    Lazy::new(|| USER_LIBRARY_DIR.join(RECIPE_REPO_DIR_NAME));
 // pub const DEFAULT_RECIPE_REPOS_DIR: &str = "%APPDATA%/AutoPkg/RecipeRepos";
 // pub const DEFAULT_RECIPE_REPOS_DIR: &str = "~/Library/Application Support/AutoPkg/RecipeRepos";
 
-pub static DEFAULT_OVERRIDES_DIR: Lazy<PathBuf> = Lazy::new(|| {
-    [
-        dirs::config_dir().unwrap(),
-        PathBuf::from(TOP_DIR_NAME),
-        PathBuf::from(RECIPE_OVERRIDES_NAME),
-    ]
-    .iter()
-    .collect()
-});
+pub static DEFAULT_OVERRIDES_DIR: Lazy<PathBuf> =
+    Lazy::new(|| USER_LIBRARY_DIR.join(RECIPE_OVERRIDES_NAME));
 // pub const DEFAULT_OVERRIDES_DIR: &str = "%APPDATA%/AutoPkg/RecipeOverrides";
 // pub const DEFAULT_OVERRIDES_DIR: &str = "~/Library/Application Support/AutoPkg/RecipeOverrides";
 
-pub static DEFAULT_RECIPE_MAP: Lazy<PathBuf> = Lazy::new(|| {
-    [
-        dirs::config_dir().unwrap(),
-        PathBuf::from(TOP_DIR_NAME),
-        PathBuf::from(RECIPE_MAP_FILENAME),
-    ]
-    .iter()
-    .collect()
-});
+pub static DEFAULT_RECIPE_MAP: Lazy<PathBuf> =
+    Lazy::new(|| USER_LIBRARY_DIR.join(RECIPE_MAP_FILENAME));
 // pub const DEFAULT_RECIPE_MAP: &str = "%APPDATA%/AutoPkg/recipe_map.json";
 // pub const DEFAULT_RECIPE_MAP: &str = "~/Library/Application Support/AutoPkg/recipe_map.json";
 
-pub static DEFAULT_GH_TOKEN_PATH: Lazy<PathBuf> = Lazy::new(|| {
-    [
-        dirs::config_dir().unwrap(),
-        PathBuf::from(TOP_DIR_NAME),
-        PathBuf::from(GH_TOKEN_FILENAME),
-    ]
-    .iter()
-    .collect()
-});
+pub static DEFAULT_GH_TOKEN_PATH: Lazy<PathBuf> =
+    Lazy::new(|| USER_LIBRARY_DIR.join(GH_TOKEN_FILENAME));
 // pub const DEFAULT_RECIPE_MAP: &str = "%APPDATA%/AutoPkg/gh_token";
 // pub const DEFAULT_RECIPE_MAP: &str = "~/Library/Application Support/AutoPkg/gh_token";
 
-pub static PREFERENCES_PATH: Lazy<PathBuf> = Lazy::new(|| {
-    [
-        dirs::config_dir().unwrap(),
-        PathBuf::from(TOP_DIR_NAME),
-        PathBuf::from(PREFERENCES_FILENAME),
-    ]
-    .iter()
-    .collect()
-});
+pub static PREFERENCES_PATH: Lazy<PathBuf> =
+    Lazy::new(|| USER_LIBRARY_DIR.join(PREFERENCES_FILENAME));
 // pub const DEFAULT_RECIPE_MAP: &str = "%APPDATA%/AutoPkg/autopkg_prefs.json";
 // pub const DEFAULT_RECIPE_MAP: &str = "~/Library/Application Support/AutoPkg/autopkg_prefs.json";
 
+pub static SYSTEM_PREFERENCES_PATH: Lazy<PathBuf> =
+    Lazy::new(|| DEFAULT_LIBRARY_DIR.join(PREFERENCES_FILENAME));
+// pub const SYSTEM_PREFERENCES_PATH: &str = "%PROGRAMDATA%/AutoPkg/autopkg_prefs.json";
+// pub const SYSTEM_PREFERENCES_PATH: &str = "/Library/Application Support/AutoPkg/autopkg_prefs.json";
+
+/// Where Python AutoPkg reads its preferences from on this platform, in the
+/// order it looks for them
+pub static PYTHON_PREFERENCES_PATHS: Lazy<Vec<PathBuf>> = Lazy::new(|| {
+    if cfg!(target_os = "macos") {
+        dirs::home_dir()
+            .map(|home| {
+                home.join("Library/Preferences")
+                    .join(format!("{PYTHON_BUNDLE_ID}.plist"))
+            })
+            .into_iter()
+            .collect()
+    } else {
+        dirs::config_local_dir()
+            .map(|dir| {
+                let dir = dir.join(PYTHON_APP_NAME);
+                vec![dir.join("config.plist"), dir.join("config.json")]
+            })
+            .unwrap_or_default()
+    }
+});
+// pub const PYTHON_PREFERENCES_PATHS: &str = "~/Library/Preferences/com.github.autopkg.plist";
+// pub const PYTHON_PREFERENCES_PATHS: &str = "~/.config/Autopkg/config.json";
+
 pub static REPO_MAP_PATH: Lazy<PathBuf> = Lazy::new(|| {
-    [
-        dirs::config_dir().unwrap(),
-        PathBuf::from(TOP_DIR_NAME),
-        PathBuf::from(RECIPE_REPO_DIR_NAME),
-        PathBuf::from(REPO_MAP_FILENAME),
-    ]
-    .iter()
-    .collect()
+    USER_LIBRARY_DIR
+        .join(RECIPE_REPO_DIR_NAME)
+        .join(REPO_MAP_FILENAME)
 });
 // pub const DEFAULT_RECIPE_MAP: &str = "%APPDATA%/AutoPkg/RecipeRepos/repo_map.json";
 // pub const DEFAULT_RECIPE_MAP: &str = "~/Library/Application Support/AutoPkg/RecipeRepos/repo_map.json";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_library_dir_fallbacks() {
+        let config_dir = Some(PathBuf::from("/config"));
+        let home_dir = Some(PathBuf::from("/home/autopkg"));
+        assert_eq!(
+            user_library_dir(
+                Some(Path::new("/bundle")),
+                config_dir.to_owned(),
+                home_dir.to_owned()
+            ),
+            PathBuf::from("/bundle")
+        );
+        assert_eq!(
+            user_library_dir(None, config_dir, home_dir.to_owned()),
+            PathBuf::from("/config/AutoPkg")
+        );
+        assert_eq!(
+            user_library_dir(None, None, home_dir),
+            PathBuf::from("/home/autopkg/.autopkg")
+        );
+        assert_eq!(
+            user_library_dir(None, None, None),
+            std::env::temp_dir().join("AutoPkg")
+        );
+    }
+}
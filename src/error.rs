@@ -0,0 +1,164 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::recipes::RecipeCollision;
+
+/// Shorthand for a Result that uses AutoPkg's Error type
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// All errors that can be returned by AutoPkg
+///
+/// Each variant maps to a distinct process exit code (see `exit_code()`), so
+/// that scripts running AutoPkg can tell failures apart without parsing the
+/// output.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// No recipe with this identifier or shortname is in the recipe map
+    #[error("Recipe {0} not found in recipe map")]
+    RecipeNotFound(String),
+    /// A file exists, but couldn't be parsed
+    #[error("Unable to parse {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: ParseError,
+    },
+    /// A recipe refers to a parent identifier that isn't in the recipe map
+    #[error("Parent recipe {parent} of {child} not found in recipe map")]
+    MissingParent { child: String, parent: String },
+    /// The ParentRecipe links loop back on themselves
+    #[error("Cyclic parent recipes: {}", .0.join(" -> "))]
+    CyclicParent(Vec<String>),
+    /// The preferences file couldn't be parsed into Preferences
+    #[error("Invalid preferences file {}: {source}", path.display())]
+    InvalidPreferences {
+        path: PathBuf,
+        #[source]
+        source: ParseError,
+    },
+    /// Checking the preferences found problems that keep AutoPkg from running
+    #[error("{0} problem(s) found with the preferences")]
+    PreferenceProblems(usize),
+    /// A preference named on the command line isn't set, or can't hold the
+    /// value it was given
+    #[error("{key}: {message}")]
+    Preference { key: String, message: String },
+    /// No processor with this name has been registered
+    #[error("Processor {0} not found")]
+    ProcessorNotFound(String),
+    /// A processor failed while running
+    #[error("{processor}: {message}")]
+    Processor { processor: String, message: String },
+    /// A recipe was loaded, but can't be run as-is
+    #[error("Invalid recipe {recipe}: {message}")]
+    InvalidRecipe { recipe: String, message: String },
+    /// A recipe override's stored trust info doesn't match its parents
+    #[error("{recipe} failed trust verification:\n{message}")]
+    TrustVerification { recipe: String, message: String },
+    /// Refusing to replace an override that's already on disk
+    #[error(
+        "A recipe override already exists at {}, will not overwrite it. Use --force to overwrite anyway",
+        .0.display()
+    )]
+    OverrideExists(PathBuf),
+    /// More than one recipe claims the same identifier, shortname or override
+    /// name, and the recipe map was built with --strict
+    #[error(
+        "{} duplicate recipe name(s) in the recipe map:\n{}",
+        .0.len(),
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n")
+    )]
+    RecipeCollisions(Vec<RecipeCollision>),
+    /// One or more recipes in a run failed
+    #[error("{0} recipe(s) failed")]
+    RecipeFailures(usize),
+    /// Reading or writing a file on disk failed
+    #[error("Unable to access {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+/// The underlying cause of a parse failure, preserving the original error
+/// from whichever parser(s) were tried
+#[derive(Debug, Error)]
+pub enum ParseError {
+    #[error(transparent)]
+    Plist(#[from] plist::Error),
+    #[error(transparent)]
+    Yaml(#[from] serde_yaml::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    /// Recipes can be either format, so when we can't tell which one it is
+    /// we keep both errors
+    #[error("not a valid plist ({plist}) or yaml ({yaml})")]
+    PlistOrYaml {
+        plist: plist::Error,
+        yaml: serde_yaml::Error,
+    },
+    /// A required key is missing or isn't a string
+    #[error("missing string value for key {0}")]
+    MissingKey(String),
+    /// Only yaml has null values, so they can't be converted to a plist
+    #[error("null values can't be stored in a plist")]
+    NullInPlist,
+}
+
+impl Error {
+    /// Wrap an I/O error with the path that caused it
+    pub fn io(path: &Path, source: io::Error) -> Error {
+        Error::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// Wrap a parser error with the path that caused it
+    pub fn parse(path: &Path, source: impl Into<ParseError>) -> Error {
+        Error::Parse {
+            path: path.to_path_buf(),
+            source: source.into(),
+        }
+    }
+
+    /// The process exit code the CLI uses for this error
+    ///
+    /// - 3: recipe not found
+    /// - 4: recipe or file could not be parsed
+    /// - 5: parent recipe not found
+    /// - 6: cyclic parent recipes
+    /// - 7: invalid preferences, or problems found by `doctor`
+    /// - 8: I/O error
+    /// - 9: processor not found
+    /// - 10: processor failed
+    /// - 11: recipe can't be run
+    /// - 12: parent trust verification failed
+    /// - 13: recipe override already exists
+    /// - 14: duplicate recipe names in a strict recipe map build
+    /// - 15: preference not set, or given an invalid value
+    /// - 70: one or more recipes failed, the same as Python AutoPkg
+    ///
+    /// 1 is reserved for general failures and 2 for command line usage errors.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::RecipeNotFound(_) => 3,
+            Error::Parse { .. } => 4,
+            Error::MissingParent { .. } => 5,
+            Error::CyclicParent(_) => 6,
+            Error::InvalidPreferences { .. } | Error::PreferenceProblems(_) => 7,
+            Error::Io { .. } => 8,
+            Error::ProcessorNotFound(_) => 9,
+            Error::Processor { .. } => 10,
+            Error::InvalidRecipe { .. } => 11,
+            Error::TrustVerification { .. } => 12,
+            Error::OverrideExists(_) => 13,
+            Error::RecipeCollisions(_) => 14,
+            Error::Preference { .. } => 15,
+            Error::RecipeFailures(_) => 70,
+        }
+    }
+}
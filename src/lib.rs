@@ -1,12 +1,20 @@
-use anyhow::Result;
+use std::collections::BTreeMap;
 use std::fmt;
-use std::path::Path;
-use std::{collections::HashMap, fs, path::PathBuf};
-use tracing::debug;
+use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
 pub mod constants;
+pub mod error;
+pub mod prefs;
+pub mod processors;
+pub mod recipes;
+pub mod runner;
+
+use error::{Error, ParseError, Result};
+use prefs::PrefsFormat;
+use processors::Environment;
+use recipes::PlistDataType;
 
 /// The Preferences object used to handle all AutoPkg preferences
 #[derive(Debug, Serialize, Deserialize)]
@@ -21,6 +29,10 @@ pub struct Preferences {
     // recipe_search_dirs. It must be stored somewhere mutable.
     /// List of directories to search when building a recipe map
     pub recipe_search_dirs: Vec<PathBuf>,
+    /// How each search dir is searched for recipes, by path. Any folder
+    /// that isn't listed uses the default settings.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub recipe_search_dir_settings: BTreeMap<PathBuf, SearchDirSettings>,
     /// Parent folder for AutoPkg's cached downloads
     #[serde(default = "default_cache_dir")]
     pub cache_dir: PathBuf,
@@ -44,9 +56,41 @@ pub struct Preferences {
     /// Path to preferences file
     #[serde(default = "default_prefs_path", skip)] // don't write this back to the prefs file
     pub prefs_path: PathBuf,
+    /// Format of the preferences file, which it's written back out in
+    #[serde(skip)]
+    pub prefs_format: PrefsFormat,
     /// Any extra keys can be added in and used within recipes or Processors.
-    /// These are not used by any native/built-in AutoPkg functions
-    pub extras: Option<HashMap<String, String>>,
+    /// These are not used by any native/built-in AutoPkg functions, and can
+    /// hold any value a plist can.
+    pub extras: Option<BTreeMap<String, PlistDataType>>,
+}
+
+/// Settings for how one folder is searched for recipes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct SearchDirSettings {
+    /// How many folders deep to look for recipes
+    #[serde(default = "default_search_depth")]
+    pub max_depth: usize,
+    /// Glob patterns of files and folders to skip. A pattern without a slash
+    /// matches a name at any depth, anything else is matched against the path
+    /// relative to the search dir.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+    /// Whether to also skip the patterns listed in any .autopkgignore file
+    /// inside the search dir
+    #[serde(default = "default_use_autopkgignore")]
+    pub use_autopkgignore: bool,
+}
+
+impl Default for SearchDirSettings {
+    fn default() -> Self {
+        SearchDirSettings {
+            max_depth: default_search_depth(),
+            ignore: Vec::new(),
+            use_autopkgignore: default_use_autopkgignore(),
+        }
+    }
 }
 
 impl Default for Preferences {
@@ -65,6 +109,22 @@ impl fmt::Display for Preferences {
             writeln!(f, "    {}", line.display())?;
         }
         writeln!(f)?;
+        if !self.recipe_search_dir_settings.is_empty() {
+            writeln!(f, "RECIPE_SEARCH_DIR_SETTINGS: ")?;
+            for (dir, settings) in &self.recipe_search_dir_settings {
+                writeln!(f, "    {}", dir.display())?;
+                writeln!(f, "        MAX_DEPTH: {}", settings.max_depth)?;
+                if !settings.ignore.is_empty() {
+                    writeln!(f, "        IGNORE: {}", settings.ignore.join(", "))?;
+                }
+                writeln!(
+                    f,
+                    "        USE_AUTOPKGIGNORE: {}",
+                    settings.use_autopkgignore
+                )?;
+            }
+            writeln!(f)?;
+        }
         writeln!(f, "CACHE_DIR: ")?;
         writeln!(f, "    {}", self.cache_dir.display())?;
         writeln!(f)?;
@@ -79,29 +139,66 @@ impl fmt::Display for Preferences {
         writeln!(f)?;
         writeln!(f, "RECIPE_MAP_PATH: ")?;
         writeln!(f, "    {}", self.recipe_map_path.display())?;
-        if self.munki_repo.is_some() {
+        if let Some(munki_repo) = &self.munki_repo {
             writeln!(f)?;
             writeln!(f, "MUNKI_REPO: ")?;
-            writeln!(f, "    {}", self.munki_repo.as_ref().unwrap().display())?;
+            writeln!(f, "    {}", munki_repo.display())?;
         }
         writeln!(f)?;
         writeln!(f, "DISABLE_CODE_SIGNATURE_VERIFICATION: ")?;
         writeln!(f, "    {}", self.disable_code_signature_verification)?;
-        if self.extras.is_some() {
+        if let Some(extras) = &self.extras {
             writeln!(f)?;
             writeln!(f, "EXTRA KEYS: ")?;
-            for (key, value) in self.extras.as_ref().unwrap().iter() {
-                writeln!(f, "    {:>20}: {:<10}", key, value)?;
+            for (key, value) in extras.iter() {
+                write!(f, "    {key}:")?;
+                write_extra_value(f, value, 2)?;
             }
         }
         Ok(())
     }
 }
 
+/// Write an extra key's value after its name, with each item of an array or
+/// dictionary on its own line, indented one level further
+fn write_extra_value(
+    f: &mut fmt::Formatter<'_>,
+    value: &PlistDataType,
+    depth: usize,
+) -> fmt::Result {
+    let indent = "    ".repeat(depth);
+    match value {
+        PlistDataType::Array(items) => {
+            writeln!(f)?;
+            for item in items {
+                write!(f, "{indent}-")?;
+                write_extra_value(f, item, depth + 1)?;
+            }
+            Ok(())
+        }
+        PlistDataType::Dict(dict) => {
+            writeln!(f)?;
+            for (key, item) in dict {
+                write!(f, "{indent}{key}:")?;
+                write_extra_value(f, item, depth + 1)?;
+            }
+            Ok(())
+        }
+        PlistDataType::Bool(value) => writeln!(f, " {value}"),
+        PlistDataType::Data(data) => writeln!(f, " <{} bytes of data>", data.len()),
+        PlistDataType::Date(date) => writeln!(f, " {}", date.to_xml_format()),
+        PlistDataType::Integer(value) => writeln!(f, " {value}"),
+        PlistDataType::Null => writeln!(f),
+        PlistDataType::Real(value) => writeln!(f, " {value}"),
+        PlistDataType::Str(value) => writeln!(f, " {value}"),
+    }
+}
+
 impl Preferences {
     pub fn new() -> Preferences {
         Preferences {
             recipe_search_dirs: vec![PathBuf::from(".")],
+            recipe_search_dir_settings: BTreeMap::new(),
             cache_dir: default_cache_dir(),
             recipe_override_dir: default_recipe_override_dir(),
             recipe_repo_dir: default_recipe_repo_dir(),
@@ -109,47 +206,95 @@ impl Preferences {
             recipe_map_path: default_recipe_map_path(),
             disable_code_signature_verification: default_disable_code_signature_verification(),
             prefs_path: default_prefs_path(),
+            prefs_format: PrefsFormat::default(),
             munki_repo: None,
             extras: None,
         }
     }
 
-    /// Read in the JSON preferences file and return a Preferences object
+    /// Read in a JSON, plist or yaml preferences file and return a
+    /// Preferences object that's written back to the same file, in the same
+    /// format
     pub fn read_from_disk(&self, path: &Path) -> Result<Preferences> {
-        // Reading the file into a string first is significantly faster than
-        // reading directly from a reader: https://github.com/serde-rs/json/issues/160
-        let json_data = fs::read_to_string(path)?;
-        let prefs: Preferences = serde_json::from_str(&json_data)?;
+        let (values, format) = prefs::read_prefs_file(path)?;
+        let mut prefs: Preferences = serde_json::from_value(serde_json::Value::Object(values))
+            .map_err(|e| Error::InvalidPreferences {
+                path: path.to_path_buf(),
+                source: ParseError::Json(e),
+            })?;
+        prefs.prefs_path = path.to_path_buf();
+        prefs.prefs_format = format;
         Ok(prefs)
     }
 
-    /// Append a path to the search dirs and write out to preferences
-    pub fn add_to_search_dirs(&mut self, path: &Path) -> Result<()> {
-        self.recipe_search_dirs.push(path.to_path_buf());
-        self.write_to_disk()?;
-        Ok(())
+    /// Return the settings for searching a folder for recipes
+    ///
+    /// Settings can be keyed by any spelling of the folder, such as
+    /// `~/Recipes` for a search dir that's stored as an absolute path.
+    pub fn search_dir_settings(&self, dir: &Path) -> SearchDirSettings {
+        self.recipe_search_dir_settings
+            .get(dir)
+            .or_else(|| {
+                self.recipe_search_dir_settings
+                    .iter()
+                    .find(|(key, _)| prefs::same_search_dir(key, dir))
+                    .map(|(_, settings)| settings)
+            })
+            .cloned()
+            .unwrap_or_default()
     }
 
-    /// Remove a path from the search dirs and write out to preferences
-    pub fn remove_from_search_dirs(&mut self, path: &Path) -> Result<()> {
-        debug!("Search dir to remove: {}", path.display());
-        // Remove all instances of matching path from the vec. This must be an
-        // exact match for it to work; does nothing if not found.
-        // debug!("Search dirs before retain: {:?}", &self.recipe_search_dirs);
-        self.recipe_search_dirs.retain(|s| *s != path);
-        // We always write out even if we didn't find a match.
-        // debug!("Search dirs after retain: {:?}", &self.recipe_search_dirs);
-        self.write_to_disk()?;
-        Ok(())
+    /// Build the starting environment for a recipe run from the preferences
+    ///
+    /// Every preference is available to recipes under its SCREAMING_SNAKE_CASE
+    /// name, the same as in the preferences file.
+    pub fn to_environment(&self) -> Environment {
+        let path_value = |path: &Path| PlistDataType::Str(path.display().to_string());
+        let mut env = Environment::from([
+            (
+                "RECIPE_SEARCH_DIRS".to_string(),
+                PlistDataType::from(
+                    self.recipe_search_dirs
+                        .iter()
+                        .map(|dir| dir.display().to_string())
+                        .collect::<Vec<String>>(),
+                ),
+            ),
+            ("CACHE_DIR".to_string(), path_value(&self.cache_dir)),
+            (
+                "RECIPE_OVERRIDE_DIR".to_string(),
+                path_value(&self.recipe_override_dir),
+            ),
+            (
+                "RECIPE_REPO_DIR".to_string(),
+                path_value(&self.recipe_repo_dir),
+            ),
+            (
+                "GITHUB_TOKEN_PATH".to_string(),
+                path_value(&self.github_token_path),
+            ),
+            (
+                "RECIPE_MAP_PATH".to_string(),
+                path_value(&self.recipe_map_path),
+            ),
+            (
+                "DISABLE_CODE_SIGNATURE_VERIFICATION".to_string(),
+                PlistDataType::Bool(self.disable_code_signature_verification),
+            ),
+        ]);
+        if let Some(munki_repo) = &self.munki_repo {
+            env.insert("MUNKI_REPO".to_string(), path_value(munki_repo));
+        }
+        // Extra keys can't replace a built-in preference
+        for (key, value) in self.extras.iter().flatten() {
+            env.entry(key.to_owned()).or_insert_with(|| value.clone());
+        }
+        env
     }
 
-    /// Write the preferences out to disk
-    /// For now, this only supports JSON
-    pub fn write_to_disk(&self) -> Result<(), std::io::Error> {
-        std::fs::write(
-            &self.prefs_path,
-            serde_json::to_string_pretty(self).unwrap(),
-        )
+    /// Write the preferences out to disk, in the format they were read in
+    pub fn write_to_disk(&self) -> Result<()> {
+        prefs::write_prefs_file(&self.prefs_path, self, self.prefs_format)
     }
 }
 
@@ -177,6 +322,14 @@ fn default_disable_code_signature_verification() -> bool {
     false
 }
 
+fn default_search_depth() -> usize {
+    3
+}
+
+fn default_use_autopkgignore() -> bool {
+    true
+}
+
 fn default_prefs_path() -> PathBuf {
     constants::PREFERENCES_PATH.to_path_buf()
 }
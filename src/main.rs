@@ -3,37 +3,86 @@
 #![allow(unused_variables)]
 #![allow(unused_imports)]
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 // use anyhow::{Error, Result};
 use std::error::Error;
 
 use clap::{Parser, Subcommand};
+use r_autopkg::constants;
+use r_autopkg::error::{self, Result};
+use r_autopkg::prefs::validate::{self, Severity};
+use r_autopkg::prefs::{self, LayeredPreferences, PrefsPaths};
+use r_autopkg::processors::{Processor, ProcessorRegistry};
+use r_autopkg::recipes::overrides::{self, OverrideOptions};
+use r_autopkg::recipes::{self, trust, PlistDataType, RecipeFilter, RecipeFormat};
+use r_autopkg::runner::{self, RecipeOutcome, RecipeResult, RunOptions};
 use r_autopkg::Preferences;
-use tracing::{debug, error, info, trace, warn};
+use std::io::IsTerminal;
+use std::sync::Mutex;
+use tracing::level_filters::LevelFilter;
+use tracing::{debug, error, info, trace, warn, Subscriber};
+use tracing_subscriber::fmt::{self, MakeWriter};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
 
 pub const APPNAME: &str = "AutoPkg";
 pub const AUTHOR: &str = "Nick McSpadden";
 pub const ABOUT: &str = "Automatically run recipes to fetch and process software";
-pub const EXTRA_HELP: &str = "This is where extra help goes";
+pub const EXTRA_HELP: &str = "\
+Exit codes:
+  0  Success
+  1  General failure
+  2  Command line usage error
+  3  Recipe not found
+  4  Recipe or file could not be parsed
+  5  Parent recipe not found
+  6  Cyclic parent recipes
+  7  Invalid preferences
+  8  I/O error
+  9  Processor not found
+  10 Processor failed
+  11 Recipe can't be run
+  12 Parent trust verification failed
+  13 Recipe override already exists
+  14 Duplicate recipe names with --strict
+  15 Preference not set or invalid
+  70 One or more recipes failed";
 
-pub mod constants;
-pub mod recipes;
 extern crate dirs;
 
 /*
 * COMMAND LINE PARSING
 */
 #[derive(Parser)]
-#[command(version="4.0", about=ABOUT, author=AUTHOR, after_help=EXTRA_HELP, name=APPNAME, long_about = None, arg_required_else_help = true)]
+#[command(version=constants::AUTOPKG_VERSION, about=ABOUT, author=AUTHOR, after_help=EXTRA_HELP, name=APPNAME, long_about = None, arg_required_else_help = true)]
 struct APcli {
     /// Sets a custom preferences file
     #[arg(short, long, value_name = "FILE")]
     prefs: Option<PathBuf>,
 
-    /// Turn debugging information on
+    /// Keep the cache, recipe repos, overrides, recipe map and preferences
+    /// in this folder instead of the user's config folder. Putting an
+    /// autopkg.portable file next to the binary does the same for the
+    /// binary's folder.
+    #[arg(long, value_name = "DIR")]
+    portable: Option<PathBuf>,
+
+    /// Turn debugging information on. Repeat for more: -d for info, -dd for
+    /// debug and -ddd for trace messages. RUST_LOG can also set the level
     #[arg(short, long, action = clap::ArgAction::Count)]
     debug: u8,
 
+    /// How log messages are written
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Also write log messages to a file, which is autopkg.log in the cache
+    /// dir unless one is given with --log-file=FILE
+    #[arg(long, value_name = "FILE", require_equals = true, num_args = 0..=1)]
+    log_file: Option<Option<PathBuf>>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -48,6 +97,8 @@ enum Commands {
         #[arg(short = 'l', long = "recipe-list", value_name = "TEXT_FILE")]
         recipelist: Option<PathBuf>,
     },
+    /// Check the preferences files and the folders they point at for problems
+    Doctor {},
     /// Get info about configuration or a recipe
     Info {
         /// Recipe name
@@ -100,13 +151,27 @@ enum Commands {
     },
     /// List recipes available locally
     ListRecipes {
-        // TODO: Consider turning this into a table
         /// Include recipe's identifier in the list
         #[arg(short, long = "with-identifiers")]
         identifiers: bool,
         /// Include recipe's path in the list
         #[arg(short, long = "with-paths")]
         paths: bool,
+        /// Only list recipes from this repo, such as com.github.autopkg.recipes
+        /// or just recipes. May be specified multiple times
+        #[arg(short, long, value_name = "REPO")]
+        repo: Vec<String>,
+        /// Only list recipes of this type, such as download, munki or pkg. May
+        /// be specified multiple times
+        #[arg(short = 't', long = "type", value_name = "TYPE")]
+        recipe_type: Vec<String>,
+        /// Print the recipes as JSON, with every detail
+        #[arg(long)]
+        json: bool,
+        /// Fail if more than one recipe has the same identifier or shortname,
+        /// instead of warning about it
+        #[arg(long)]
+        strict: bool,
     },
     /// List installed recipe repos
     #[clap(visible_alias = "repo-list")]
@@ -147,6 +212,11 @@ enum Commands {
         #[arg(long, value_name = "FORMAT", default_value_t = Format::Yaml)]
         format: Format,
     },
+    /// Show AutoPkg preferences
+    Prefs {
+        #[command(subcommand)]
+        command: PrefsCommand,
+    },
     /// Get information about a specific processor
     ProcessorInfo {
         /// Name of processor
@@ -178,14 +248,15 @@ enum Commands {
     },
     /// Run one or more recipes. Example: autopkg run Firefox.munki
     Run {
-        /// Recipe name
-        recipe: String,
+        /// Recipe names or identifiers
+        #[arg(required_unless_present = "recipelist")]
+        recipes: Vec<String>,
         /// Name of a processor to run before each recipe. Can be repeated to run multiple preprocessors
         #[arg(short = 'r', long, value_name = "PREPROCESSOR")]
-        preprocessor: Option<String>,
+        preprocessor: Vec<String>,
         /// Name of a processor to run after each recipe. Can be repeated to run multiple postprocessors
         #[arg(short = 'o', long, value_name = "POSTPROCESSOR")]
-        postprocessor: Option<String>,
+        postprocessor: Vec<String>,
         /// Only check for new/changed downloads
         #[arg(short, long)]
         check: bool,
@@ -218,15 +289,22 @@ enum Commands {
         #[arg(short, long = "use-token")]
         token: Option<String>,
     },
+    /// List, add or remove recipe search dirs
+    SearchDirs {
+        #[command(subcommand)]
+        command: SearchDirsCommand,
+    },
     /// Update or add parent recipe trust info for a recipe override
     UpdateTrustInfo {
-        /// Recipe override name. Must be an existing override file - use 'make-override' to create one first
-        recipe: String,
+        /// Recipe override names. Must be existing override files - use 'make-override' to create one first
+        #[arg(required = true)]
+        recipes: Vec<String>,
     },
     /// Verify parent recipe trust info for a recipe override
     VerifyTrustInfo {
-        /// Recipe override name. Must be an existing override file
-        recipe: String,
+        /// Recipe override names. Must be existing override files
+        #[arg(required_unless_present = "recipelist")]
+        recipes: Vec<String>,
         /// Verbose output. May be specified multiple times
         #[arg(short, long, action = clap::ArgAction::Count)]
         verbose: u8,
@@ -240,6 +318,76 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum PrefsCommand {
+    /// List every preference and its value
+    List {
+        /// Show which file or environment variable each value came from
+        #[arg(short, long)]
+        sources: bool,
+        /// Print the preferences as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the value of one preference
+    Get {
+        /// Preference name, such as CACHE_DIR
+        key: String,
+    },
+    /// Change one preference in the preferences file
+    Set {
+        /// Preference name, such as CACHE_DIR. Any name that isn't a built-in
+        /// preference is set as an extra key
+        key: String,
+        /// New value, read the same way as the AUTOPKG_ environment variable
+        /// for a built-in preference, and as JSON or a string otherwise
+        value: String,
+    },
+    /// Remove one preference from the preferences file, putting a built-in
+    /// preference back to its default
+    Unset {
+        /// Preference name, such as CACHE_DIR
+        key: String,
+    },
+    /// Print the path of the preferences file changes are saved to
+    Path,
+    /// Import the preferences of an existing Python AutoPkg installation
+    Import {
+        /// Python AutoPkg preferences files to import. Defaults to the ones
+        /// Python AutoPkg uses on this platform.
+        files: Vec<PathBuf>,
+        /// Show the imported preferences without writing them out
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line, including the recipe and processor running
+    Json,
+}
+
+#[derive(Subcommand)]
+enum SearchDirsCommand {
+    /// List the folders searched for recipes, in the order they're searched
+    List,
+    /// Add folders to the end of the search dirs
+    Add {
+        /// Folders to add. ~ is expanded and relative paths are made absolute
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Remove folders from the search dirs, however they're spelled there
+    Remove {
+        /// Folders to remove
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
 enum Format {
     /// Property List format
@@ -257,6 +405,122 @@ impl std::fmt::Display for Format {
     }
 }
 
+/// Print every preference as KEY: value, optionally followed by the layer it
+/// came from
+fn print_prefs(layered: &LayeredPreferences, sources: bool, json: bool) {
+    let Ok(serde_json::Value::Object(values)) = serde_json::to_value(&layered.prefs) else {
+        return;
+    };
+    if json {
+        let output: serde_json::Map<String, serde_json::Value> = if sources {
+            values
+                .into_iter()
+                .map(|(key, value)| {
+                    let source = layered.source(&key).to_string();
+                    (key, serde_json::json!({"value": value, "source": source}))
+                })
+                .collect()
+        } else {
+            values
+        };
+        println!("{:#}", serde_json::Value::Object(output));
+        return;
+    }
+    for (key, value) in values {
+        if sources {
+            println!("{key}: {value} ({})", layered.source(&key));
+        } else {
+            println!("{key}: {value}");
+        }
+    }
+}
+
+/// Print recipe shortnames, with their identifiers and paths in aligned
+/// columns if asked for
+fn print_recipe_table(listings: &[recipes::RecipeListing], identifiers: bool, paths: bool) {
+    let width = |column: fn(&recipes::RecipeListing) -> usize| {
+        listings.iter().map(column).max().unwrap_or(0)
+    };
+    let shortname_width = width(|listing| listing.shortname.len());
+    let identifier_width = width(|listing| listing.identifier.len());
+    for listing in listings {
+        let mut row = format!("{:shortname_width$}", listing.shortname);
+        if identifiers {
+            row.push_str(&format!("  {:identifier_width$}", listing.identifier));
+        }
+        if paths {
+            row.push_str(&format!("  {}", listing.path.display()));
+        }
+        println!("{}", row.trim_end());
+    }
+}
+
+/// Print every problem found with the preferences, failing if any of them
+/// keep AutoPkg from running
+fn run_doctor(command_line: Option<&Path>) -> Result<()> {
+    let problems = validate::diagnose(&PrefsPaths::standard(command_line), std::env::vars());
+    if problems.is_empty() {
+        println!("No problems found with the preferences");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{problem}");
+    }
+    let errors = problems
+        .iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        return Err(error::Error::PreferenceProblems(errors));
+    }
+    Ok(())
+}
+
+/// Import Python AutoPkg preferences into the preferences file changes are
+/// written to, leaving anything they don't set alone
+fn import_prefs(current: &Preferences, files: &[PathBuf], dry_run: bool) -> Result<()> {
+    let files: Vec<PathBuf> = if files.is_empty() {
+        constants::PYTHON_PREFERENCES_PATHS
+            .iter()
+            .filter(|path| path.is_file())
+            .cloned()
+            .collect()
+    } else {
+        files.to_vec()
+    };
+    if files.is_empty() {
+        warn!("No Python AutoPkg preferences found to import");
+        return Ok(());
+    }
+
+    let mut imported = prefs::read_writable_prefs(current)?;
+    for file in &files {
+        let (values, _) = prefs::read_prefs_file(file)?;
+        let report = prefs::import_python_prefs(&mut imported, values)?;
+        println!("Imported from {}:", file.display());
+        for key in &report.imported {
+            println!("    {key}");
+        }
+        for key in &report.extras {
+            println!("    {key} (as an extra key)");
+        }
+        if !report.skipped.is_empty() {
+            println!("Not imported:");
+            for (key, reason) in &report.skipped {
+                println!("    {key}: {reason}");
+            }
+        }
+    }
+
+    if dry_run {
+        print!("{}", imported.to_preferences()?);
+    } else {
+        imported.write_to_disk()?;
+        println!("Preferences saved to {}", imported.path.display());
+    }
+    Ok(())
+}
+
 /// Parse a single key-value pair
 // Taken directly from https://docs.rs/clap/latest/clap/_derive/_cookbook/typed_derive/index.html
 fn parse_key_value<T, U>(s: &str) -> Result<(T, U), Box<dyn Error + Send + Sync + 'static>>
@@ -278,52 +542,197 @@ where
 
 /* LOGGING AND TRACING LOGIC */
 
+/// The log file layer, which is empty until the preferences say where the
+/// cache dir is
+type FileLayer = Box<dyn Layer<Registry> + Send + Sync>;
+type LogFileHandle = reload::Handle<Option<FileLayer>, Registry>;
+
 /// Configure the 'tracing_subscriber' for logging across the app
-fn configure_tracing() {
+///
+/// Send log messages to stderr at the level -d or RUST_LOG asks for, returning
+/// a handle that can also start writing them to a file later. Colors are only
+/// used when stderr is a terminal.
+fn configure_tracing(debug: u8, format: LogFormat) -> LogFileHandle {
+    let level = match debug {
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    // RUST_LOG can fine-tune what's logged, such as
+    // RUST_LOG=r_autopkg::recipes=debug, but -d always sets the overall level
+    let mut filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+    if debug > 0 {
+        filter = filter.add_directive(level.into());
+    }
+    let (file_layer, handle) = reload::Layer::new(None);
+    tracing_subscriber::registry()
+        .with(file_layer)
+        .with(filter)
+        .with(log_layer(
+            format,
+            std::io::stderr,
+            std::io::stderr().is_terminal(),
+        ))
+        // sets this to be the default, global collector for this application.
+        .init();
+    handle
+}
+
+/// Build a layer that writes log messages in the given format
+fn log_layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
     // Tracing guide copied from https://www.hamzak.xyz/blog-posts/tracing-in-rust-a-comprehensive-guide
-    tracing_subscriber::fmt()
-        // enable everything
-        .with_max_level(tracing::Level::TRACE)
-        .compact()
+    let layer = fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi)
         // Display source code file paths
         .with_file(true)
         // Display source code line numbers
         .with_line_number(true)
-        // Display the thread ID an event was recorded on
-        // .with_thread_ids(true)
         // Don't display the event's target (module path)
-        .with_target(false)
-        // sets this to be the default, global collector for this application.
-        .init();
+        .with_target(false);
+    match format {
+        LogFormat::Text => layer.compact().boxed(),
+        // Every event carries the recipe and processor it happened in
+        LogFormat::Json => layer
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    }
+}
+
+/// Start appending log messages to a file as well as stderr
+fn start_log_file(handle: &LogFileHandle, path: &Path, format: LogFormat) -> Result<()> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent).map_err(|e| error::Error::io(parent, e))?;
+    }
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| error::Error::io(path, e))?;
+    if let Err(e) = handle.reload(Some(log_layer(format, Mutex::new(file), false))) {
+        warn!("Unable to log to {}: {e}", path.display());
+    }
+    debug!("Logging to {}", path.display());
+    Ok(())
 }
 
 /* end LOGGING AND TRACING LOGIC */
 
-fn main() {
-    // This allows us to use simple macros like debug! and log!
-    configure_tracing();
+/// Print a processor's description and variables, the same way Python
+/// AutoPkg's processor-info does
+fn print_processor_info(processor: &dyn Processor) {
+    println!("Description: {}", processor.description());
+    println!("Input variables:");
+    for variable in processor.input_variables() {
+        println!("   {}:", variable.name);
+        println!("     required: {}", variable.required);
+        println!("     description: {}", variable.description);
+        if let Some(default) = variable.default {
+            println!("     default: {default}");
+        }
+    }
+    println!("Output variables:");
+    for variable in processor.output_variables() {
+        println!("   {}:", variable.name);
+        println!("     description: {}", variable.description);
+    }
+}
+
+/// Print which recipes failed, stopped early, or found something new, like
+/// the summary at the end of a Python AutoPkg run
+fn print_run_summary(results: &[RecipeResult]) {
+    let mut failed = Vec::new();
+    let mut new_downloads = Vec::new();
+    for result in results {
+        match &result.outcome {
+            RecipeOutcome::Failed(e) => failed.push(format!("    {}: {e}", result.recipe)),
+            RecipeOutcome::NewDownload => new_downloads.push(format!("    {}", result.recipe)),
+            RecipeOutcome::Success | RecipeOutcome::Stopped => {}
+        }
+    }
+    println!();
+    if !failed.is_empty() {
+        println!("The following recipes failed:");
+        println!("{}", failed.join("\n"));
+    }
+    if !new_downloads.is_empty() {
+        println!("The following new items were downloaded:");
+        println!("{}", new_downloads.join("\n"));
+    }
+    if failed.is_empty() && new_downloads.is_empty() {
+        println!("Nothing downloaded, packaged or imported.");
+    }
+}
 
+fn main() -> ExitCode {
     let cli: APcli = APcli::parse();
 
-    // Create a new preferences object
-    let mut prefs = Preferences::new();
-    if let Some(prefs_argument) = cli.prefs.as_deref() {
-        info!(
-            "Canonicalized preferences file path: {}",
-            prefs_argument.canonicalize().unwrap().display()
-        );
-        let prefs_path = PathBuf::from(prefs_argument);
-        prefs = prefs.read_from_disk(&prefs_path).unwrap();
-        prefs.prefs_path = prefs_path;
+    // This allows us to use simple macros like debug! and log!
+    let log_file = configure_tracing(cli.debug, cli.log_format);
+
+    // Every error maps to its own exit code, documented in EXTRA_HELP
+    match run(&cli, &log_file) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{e}");
+            ExitCode::from(e.exit_code())
+        }
     }
+}
 
-    // Measure debug level
-    // TODO: Hook this up to actual log level in configure_tracing()
-    match cli.debug {
-        0 => println!("Debug mode is off"),
-        1 => println!("Debug mode is kind of on"),
-        2 => println!("Debug mode is on"),
-        _ => println!("Don't be crazy"),
+/// Load the preferences and handle the subcommand given on the command line
+fn run(cli: &APcli, log_file: &LogFileHandle) -> Result<()> {
+    // Portable mode has to be set up before any AutoPkg folder is looked up
+    if let Some(root) = &cli.portable {
+        let root = std::path::absolute(root).map_err(|e| error::Error::io(root, e))?;
+        if !constants::set_portable_root(root.to_owned()) {
+            return Err(error::Error::Preference {
+                key: "--portable".to_string(),
+                message: format!(
+                    "can't keep everything in {}, because AutoPkg's folders were already looked up",
+                    root.display()
+                ),
+            });
+        }
+    }
+    if let Some(root) = constants::portable_root() {
+        info!("Portable mode, using {}", root.display());
+    }
+
+    // The doctor checks the preferences files themselves, so it runs before
+    // they're loaded
+    if let Some(Commands::Doctor {}) = &cli.command {
+        return run_doctor(cli.prefs.as_deref());
+    }
+
+    // Layer the preferences files and environment, with --prefs on top
+    let layered = prefs::load_preferences(cli.prefs.as_deref())?;
+    info!(
+        "Preferences file path: {}",
+        layered.prefs.prefs_path.display()
+    );
+    let prefs = &layered.prefs;
+    if let Some(path) = &cli.log_file {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => prefs.cache_dir.join(constants::LOG_FILENAME),
+        };
+        start_log_file(log_file, &path, cli.log_format)?;
+    }
+    for problem in validate::check_preferences(prefs) {
+        warn!("{problem}");
     }
 
     // Handle all CLI subcommands
@@ -337,6 +746,8 @@ fn main() {
                 println!("Auditing recipe: {}", recipe);
             }
         }
+        // Handled before the preferences are loaded
+        Some(Commands::Doctor {}) => {}
         Some(Commands::Info { quiet, recipe }) => {
             // This would be from "info --quiet <recipe>"
             if *quiet {
@@ -427,27 +838,45 @@ fn main() {
             }
         }
         Some(Commands::ListProcessors { core, custom }) => {
-            if *core {
-                // This would be from "list-processors -o"
-                println!("Listing core processors");
-            } else if *custom {
-                // This would be from "list-processors -c"
-                println!("Listing custom processors");
-            } else {
-                // This is if neither -o nor -c are specified as flags
-                println!("Listing all processors");
+            let registry = ProcessorRegistry::new();
+            let mut processor_names = Vec::new();
+            // This would be from "list-processors -o", or neither flag
+            if *core || !*custom {
+                processor_names.extend(registry.core_processor_names());
+            }
+            // This would be from "list-processors -c", or neither flag
+            if *custom || !*core {
+                processor_names.extend(registry.custom_processor_names());
+            }
+            processor_names.sort();
+            for name in processor_names {
+                println!("{name}");
             }
         }
-        Some(Commands::ListRecipes { identifiers, paths }) => {
-            if *identifiers {
-                // This would be from "list-recipes -i"
-                println!("Listing recipes with identifiers");
-            } else if *paths {
-                // This would be from "list-recipes -p"
-                println!("Listing recipes with paths");
+        Some(Commands::ListRecipes {
+            identifiers,
+            paths,
+            repo,
+            recipe_type,
+            json,
+            strict,
+        }) => {
+            // The map is always rebuilt, so collisions are reported even when
+            // the map on disk is up to date. Unchanged recipes aren't read again.
+            // With --strict, a collision is an error instead of a warning
+            let (map, collisions) = recipes::build_recipe_map(prefs, *strict)?;
+            for collision in &collisions {
+                warn!("{collision}");
+            }
+            let filter = RecipeFilter {
+                repos: repo.to_owned(),
+                types: recipe_type.to_owned(),
+            };
+            let listings = recipes::list_recipes(&map, prefs, &filter);
+            if *json {
+                println!("{:#}", serde_json::json!(listings));
             } else {
-                // This is if neither -i nor -p are specified as flags
-                println!("Listing recipes");
+                print_recipe_table(&listings, *identifiers, *paths);
             }
         }
         Some(Commands::ListRepos {}) => {
@@ -462,29 +891,18 @@ fn main() {
             recipe,
         }) => {
             // This would be from "make-override <recipe>"
-            println!("Making override for recipe: {}", recipe);
-            if let Some(name) = name {
-                // This would be from "make-override --name <name>"
-                println!("Override name: {}", name);
-            } else {
-                // This is if --name is not specified as a flag
-                println!("No override name");
-            }
-            if *force {
-                // This would be from "make-override --force"
-                println!("Forcing override creation");
-            } else {
-                // This is if --force is not specified as a flag
-                println!("Not forcing override creation");
-            }
-            if *ignoredeprecation {
-                // This would be from "make-override --ignore-deprecation"
-                println!("Ignoring deprecation");
-            } else {
-                // This is if --ignore-deprecation is not specified as a flag
-                println!("Not ignoring deprecation");
-            }
-            println!("Format: {}", format);
+            let options = OverrideOptions {
+                name: name.to_owned(),
+                force: *force,
+                ignore_deprecation: *ignoredeprecation,
+                format: match format {
+                    Format::Plist => RecipeFormat::Plist,
+                    Format::Yaml => RecipeFormat::Yaml,
+                },
+            };
+            let registry = ProcessorRegistry::new();
+            let override_path = overrides::make_override(recipe, prefs, &registry, &options)?;
+            println!("Override file saved to {}", override_path.display());
         }
         Some(Commands::NewRecipe {
             identifier,
@@ -502,13 +920,45 @@ fn main() {
             }
             println!("Format: {}", format);
         }
+        Some(Commands::Prefs { command }) => match command {
+            PrefsCommand::List { sources, json } => print_prefs(&layered, *sources, *json),
+            PrefsCommand::Get { key } => match prefs::get_preference(prefs, key) {
+                Some(serde_json::Value::String(value)) => println!("{value}"),
+                Some(value) => println!("{value:#}"),
+                None => {
+                    return Err(error::Error::Preference {
+                        key: key.to_owned(),
+                        message: "not set".to_string(),
+                    })
+                }
+            },
+            PrefsCommand::Set { key, value } => {
+                let mut writable = prefs::read_writable_prefs(prefs)?;
+                prefs::set_preference(&mut writable, key, value)?;
+                writable.write_to_disk()?;
+                info!("Set {key} in {}", writable.path.display());
+            }
+            PrefsCommand::Unset { key } => {
+                let mut writable = prefs::read_writable_prefs(prefs)?;
+                prefs::unset_preference(&mut writable, key)?;
+                writable.write_to_disk()?;
+                info!("Unset {key} in {}", writable.path.display());
+            }
+            PrefsCommand::Path => println!("{}", prefs.prefs_path.display()),
+            PrefsCommand::Import { files, dry_run } => import_prefs(prefs, files, *dry_run)?,
+        },
         Some(Commands::ProcessorInfo { processor }) => {
+            let registry = ProcessorRegistry::new();
             if let Some(processor) = processor {
                 // This would be from "processor-info <processor>"
-                println!("Getting info for processor: {}", processor);
+                print_processor_info(registry.get(processor)?);
             } else {
                 // This is if <processor> is not specified
-                println!("Getting info for all processors");
+                for name in registry.core_processor_names() {
+                    println!("{name}");
+                    print_processor_info(registry.get(name)?);
+                    println!();
+                }
             }
         }
         Some(Commands::RepoAdd { recipe_repo_url }) => {
@@ -535,73 +985,60 @@ fn main() {
             pkg,
             reportplist,
             quiet,
-            recipe,
+            recipes,
         }) => {
-            // This would be from "run --check <recipe>"
-            if *check {
-                println!("Checking for new/changed downloads");
-            } else {
-                // This is if --check is not specified as a flag
-                println!("Not checking for new/changed downloads");
-            }
-            if let Some(preprocessor) = preprocessor {
-                // This would be from "run -r <preprocessor>"
-                println!("Preprocessor: {}", preprocessor);
-            } else {
-                // This is if -r is not specified as a flag
-                println!("No preprocessor");
-            }
-            if let Some(postprocessor) = postprocessor {
-                // This would be from "run -o <postprocessor>"
-                println!("Postprocessor: {}", postprocessor);
-            } else {
-                // This is if -o is not specified as a flag
-                println!("No postprocessor");
-            }
-            if *ignore {
-                // This would be from "run --ignore-parent-trust-verification-errors"
-                println!("Ignoring parent trust verification errors");
-            } else {
-                // This is if --ignore-parent-trust-verification-errors is not specified as a flag
-                println!("Not ignoring parent trust verification errors");
-            }
+            let mut options = RunOptions {
+                check_only: *check,
+                ignore_trust_verification_errors: *ignore,
+                preprocessors: preprocessor.to_owned(),
+                postprocessors: postprocessor.to_owned(),
+                ..Default::default()
+            };
+            let mut recipe_names = recipes.to_owned();
             if let Some(recipelist) = recipelist {
-                // This would be from "run <recipe> -l <recipelist>"
-                println!("Running recipes from list: {}", recipelist.display());
-            } else {
-                // This is if -l is not specified as a flag
-                println!("Running recipe: {}", recipe);
+                // This would be from "run -l <recipelist>"
+                let recipe_list = runner::parse_recipe_list(recipelist)?;
+                recipe_names.extend(recipe_list.recipes);
+                options.preprocessors.extend(recipe_list.preprocessors);
+                options.postprocessors.extend(recipe_list.postprocessors);
+                options.cli_values.extend(recipe_list.values);
+            }
+            // Values from the command line win over values in a recipe list
+            for (k, v) in key.iter().flatten() {
+                options
+                    .cli_values
+                    .insert(k.to_owned(), PlistDataType::Str(v.to_owned()));
             }
             if let Some(pkg) = pkg {
-                // This would be from "run <recipe> <pkg>"
-                println!("Providing pkg/dmg: {}", pkg.display());
-            } else {
-                // This is if <pkg> is not specified
-                println!("No pkg/dmg provided");
+                // This would be from "run <recipe> --pkg <pkg>"
+                options.cli_values.insert(
+                    "PKG".to_string(),
+                    PlistDataType::Str(pkg.display().to_string()),
+                );
             }
-            if let Some(reportplist) = reportplist {
-                // This would be from "run <recipe> --report-plist <reportplist>"
-                println!("Saving run report plist to: {}", reportplist.display());
-            } else {
-                // This is if --report
-                println!("No report plist saved");
+
+            let registry = ProcessorRegistry::new();
+            let results = runner::run_recipes(&recipe_names, prefs, &registry, &options);
+            print_run_summary(&results);
+            if !*quiet {
+                for result in &results {
+                    if let RecipeOutcome::Failed(error::Error::RecipeNotFound(name)) =
+                        &result.outcome
+                    {
+                        println!("To search GitHub for {name}, run: autopkg search {name}");
+                    }
+                }
             }
-            if *quiet {
-                // This would be from "run <recipe> --quiet"
-                println!("Quiet mode is on");
-            } else {
-                // This is if --quiet is not specified as a flag
-                println!("Quiet mode is off");
+            if let Some(reportplist) = reportplist {
+                // This would be from "run <recipe> --report-plist <path>"
+                runner::write_report_plist(reportplist, &results)?;
             }
-            if let Some(key) = key {
-                // This would be from "run <recipe> -k <key>"
-                println!("-k pair specified:");
-                for (k, v) in key {
-                    println!("{}: {}", k, v);
-                }
-            } else {
-                // This is if -k is not specified as a flag
-                println!("No key/value pairs provided");
+            let failures = results
+                .iter()
+                .filter(|result| matches!(result.outcome, RecipeOutcome::Failed(_)))
+                .count();
+            if failures > 0 {
+                return Err(error::Error::RecipeFailures(failures));
             }
         }
         Some(Commands::Search { search_term, token }) => {
@@ -615,32 +1052,78 @@ fn main() {
                 println!("Not using token");
             }
         }
-        Some(Commands::UpdateTrustInfo { recipe }) => {
-            // This would be from "update-trust-info <recipe>"
-            println!("Updating trust info for recipe: {}", recipe);
+        Some(Commands::SearchDirs { command }) => match command {
+            SearchDirsCommand::List => {
+                for dir in &prefs.recipe_search_dirs {
+                    println!("{}", dir.display());
+                }
+            }
+            SearchDirsCommand::Add { paths } => {
+                let mut writable = prefs::read_writable_prefs(prefs)?;
+                for path in paths {
+                    writable.add_to_search_dirs(path)?;
+                    info!("Added {} to the search dirs", path.display());
+                }
+            }
+            SearchDirsCommand::Remove { paths } => {
+                let mut writable = prefs::read_writable_prefs(prefs)?;
+                for path in paths {
+                    let removed = writable.remove_from_search_dirs(path)?;
+                    if removed.is_empty() {
+                        warn!("{} isn't a search dir", path.display());
+                    }
+                    for dir in removed {
+                        info!("Removed {} from the search dirs", dir.display());
+                    }
+                }
+            }
+        },
+        Some(Commands::UpdateTrustInfo { recipes }) => {
+            // This would be from "update-trust-info <recipe>..."
+            let registry = ProcessorRegistry::new();
+            for recipe in recipes {
+                let recipe_path = trust::update_trust_info(recipe, prefs, &registry)?;
+                println!("Wrote updated {}", recipe_path.display());
+            }
         }
         Some(Commands::VerifyTrustInfo {
-            recipe,
+            recipes,
             verbose,
             recipelist,
         }) => {
-            // This would be from "verify-trust-info <recipe>"
-            println!("Verifying trust info for recipe: {}", recipe);
-            match verbose {
-                0 => println!("Verbose mode is off"),
-                1 => println!("Verbose mode is 1"),
-                2 => println!("Verbose mode is 2"),
-                _ => println!("HOLY SMOKES THAT'S SOME VERBOSITY"),
-            }
+            // This would be from "verify-trust-info <recipe>..."
+            let mut recipe_names = recipes.to_owned();
             if let Some(recipelist) = recipelist {
-                // This would be from "verify-trust-info <recipe> -l <recipelist>"
-                println!(
-                    "Verifying trust info for recipes from list: {}",
-                    recipelist.display()
-                );
-            } else {
-                // This is if -l is not specified as a flag
-                println!("Verifying trust info for single recipe");
+                // This would be from "verify-trust-info -l <recipelist>"
+                recipe_names.extend(runner::parse_recipe_list(recipelist)?.recipes);
+            }
+            let registry = ProcessorRegistry::new();
+            let mut failures = 0;
+            for recipe_name in &recipe_names {
+                let verified = recipes::load_recipe(recipe_name, prefs).and_then(|recipe| {
+                    trust::verify_parent_trust(&recipe, prefs, &registry, *verbose)
+                });
+                match verified {
+                    Ok(()) => println!("{recipe_name}: OK"),
+                    Err(e) => {
+                        failures += 1;
+                        println!("{recipe_name}: FAILED");
+                        if *verbose > 0 {
+                            // Only print the details, since the recipe name
+                            // is already on the line above
+                            let details = match e {
+                                error::Error::TrustVerification { message, .. } => message,
+                                e => e.to_string(),
+                            };
+                            for line in details.lines() {
+                                println!("    {line}");
+                            }
+                        }
+                    }
+                }
+            }
+            if failures > 0 {
+                return Err(error::Error::RecipeFailures(failures));
             }
         }
         Some(Commands::Version {}) => {
@@ -651,11 +1134,10 @@ fn main() {
     }
 
     // Continued program logic goes here...
-    // Let's display the preferences file!
-    println!("Preferences file: {}", prefs);
     // trace!("Trace message");
     // debug!("Debug message");
     // info!("Info message");
     // warn!("Warning message");
     // error!("Error message");
+    Ok(())
 }
@@ -0,0 +1,1490 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use tracing::{debug, warn};
+
+use crate::constants::{
+    portable_root, PREFERENCES_PATH, PROJECT_PREFERENCES_FILENAME, SYSTEM_PREFERENCES_PATH,
+};
+use crate::error::{Error, ParseError, Result};
+use crate::recipes::PlistDataType;
+use crate::Preferences;
+
+pub mod validate;
+
+/// Prefix of environment variables that set preferences, such as
+/// AUTOPKG_CACHE_DIR
+pub const ENV_PREFIX: &str = "AUTOPKG_";
+
+/// How a preference's value is read from an AUTOPKG_ environment variable,
+/// or from the command line
+#[derive(Clone, Copy)]
+enum ValueKind {
+    /// A plain string or path
+    Str,
+    /// A list of paths, separated the same way as PATH
+    Paths,
+    /// true/false, yes/no or 1/0
+    Bool,
+    /// A JSON dictionary
+    Json,
+}
+
+/// Every built-in preference, all of which can be set from the environment
+/// or the command line
+const PREFERENCE_KINDS: [(&str, ValueKind); 10] = [
+    ("RECIPE_SEARCH_DIRS", ValueKind::Paths),
+    ("RECIPE_SEARCH_DIR_SETTINGS", ValueKind::Json),
+    ("CACHE_DIR", ValueKind::Str),
+    ("RECIPE_OVERRIDE_DIR", ValueKind::Str),
+    ("RECIPE_REPO_DIR", ValueKind::Str),
+    ("GITHUB_TOKEN_PATH", ValueKind::Str),
+    ("RECIPE_MAP_PATH", ValueKind::Str),
+    ("MUNKI_REPO", ValueKind::Str),
+    ("DISABLE_CODE_SIGNATURE_VERIFICATION", ValueKind::Bool),
+    ("EXTRAS", ValueKind::Json),
+];
+
+impl ValueKind {
+    /// Read a value of this kind from a string, or None if it isn't one
+    fn parse(self, raw: &str) -> Option<Value> {
+        match self {
+            ValueKind::Str => Some(Value::String(raw.to_string())),
+            ValueKind::Paths => Some(Value::from(
+                env::split_paths(raw)
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>(),
+            )),
+            ValueKind::Bool => match raw.to_lowercase().as_str() {
+                "1" | "true" | "yes" => Some(Value::Bool(true)),
+                "0" | "false" | "no" => Some(Value::Bool(false)),
+                _ => None,
+            },
+            ValueKind::Json => match serde_json::from_str(raw) {
+                Ok(value @ Value::Object(_)) => Some(value),
+                _ => None,
+            },
+        }
+    }
+
+    /// What a value of this kind has to be, for error messages
+    fn expected(self) -> &'static str {
+        match self {
+            ValueKind::Str => "a string",
+            ValueKind::Paths => "a list of paths",
+            ValueKind::Bool => "true or false",
+            ValueKind::Json => "a JSON dictionary",
+        }
+    }
+}
+
+/// Return how a built-in preference's value is read, or None if there's no
+/// such preference
+fn preference_kind(key: &str) -> Option<ValueKind> {
+    PREFERENCE_KINDS
+        .iter()
+        .find(|(preference, _)| *preference == key)
+        .map(|(_, kind)| *kind)
+}
+
+/// Where a preference's value came from.
+///
+/// Layers are listed from lowest to highest precedence, so a value from a
+/// later layer always replaces one from an earlier layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrefsSource {
+    /// Nothing set it, so it has its built-in default
+    Default,
+    /// The system-wide preferences file
+    System(PathBuf),
+    /// The user's preferences file
+    User(PathBuf),
+    /// A preferences file in the current folder or one of its parents
+    Project(PathBuf),
+    /// An AUTOPKG_ environment variable, by name
+    Environment(String),
+    /// The file given with --prefs
+    CommandLine(PathBuf),
+}
+
+impl fmt::Display for PrefsSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrefsSource::Default => write!(f, "default"),
+            PrefsSource::System(path) => write!(f, "system file {}", path.display()),
+            PrefsSource::User(path) => write!(f, "user file {}", path.display()),
+            PrefsSource::Project(path) => write!(f, "project file {}", path.display()),
+            PrefsSource::Environment(name) => write!(f, "environment variable {name}"),
+            PrefsSource::CommandLine(path) => write!(f, "--prefs file {}", path.display()),
+        }
+    }
+}
+
+/// The files preferences are layered from. Any file that's None, or that
+/// doesn't exist, is skipped, except for the --prefs file, which must exist.
+#[derive(Debug, Default)]
+pub struct PrefsPaths {
+    pub system: Option<PathBuf>,
+    pub user: Option<PathBuf>,
+    pub project: Option<PathBuf>,
+    pub command_line: Option<PathBuf>,
+}
+
+impl PrefsPaths {
+    /// The standard preferences files, along with the --prefs file if one
+    /// was given. The system-wide file is skipped in portable mode, which
+    /// only uses what's in the portable folder.
+    pub fn standard(command_line: Option<&Path>) -> PrefsPaths {
+        PrefsPaths {
+            system: portable_root()
+                .is_none()
+                .then(|| SYSTEM_PREFERENCES_PATH.to_path_buf()),
+            user: Some(PREFERENCES_PATH.to_path_buf()),
+            project: env::current_dir()
+                .ok()
+                .and_then(|dir| find_project_prefs(&dir)),
+            command_line: command_line.map(Path::to_path_buf),
+        }
+    }
+}
+
+/// Preferences merged from every layer, along with where each value came
+/// from
+#[derive(Debug)]
+pub struct LayeredPreferences {
+    pub prefs: Preferences,
+    /// The layer that set each preference, by its SCREAMING_SNAKE_CASE name
+    pub sources: BTreeMap<String, PrefsSource>,
+}
+
+impl LayeredPreferences {
+    /// Return where a preference came from, which is the default for any
+    /// preference that no layer set
+    pub fn source(&self, key: &str) -> &PrefsSource {
+        self.sources.get(key).unwrap_or(&PrefsSource::Default)
+    }
+}
+
+/// Look for a project preferences file in a folder and each of its parents,
+/// returning the closest one
+pub fn find_project_prefs(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|folder| folder.join(PROJECT_PREFERENCES_FILENAME))
+        .find(|path| path.is_file())
+}
+
+/// Load the preferences from the standard files, the AUTOPKG_ environment
+/// variables and the --prefs file, if there is one
+pub fn load_preferences(command_line: Option<&Path>) -> Result<LayeredPreferences> {
+    load_layered_preferences(&PrefsPaths::standard(command_line), env::vars())
+}
+
+/// Merge preferences from every layer, from lowest to highest precedence:
+/// the defaults, the system file, the user file, the project file, the
+/// AUTOPKG_ environment variables, and the --prefs file.
+///
+/// Each layer replaces whole top-level values, so a RECIPE_SEARCH_DIRS from
+/// the user file replaces the system one instead of adding to it.
+pub fn load_layered_preferences(
+    paths: &PrefsPaths,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<LayeredPreferences> {
+    let defaults = Preferences::new();
+    let mut merged = to_values(&defaults)?;
+    let mut sources = BTreeMap::new();
+    // Changes are written to the --prefs file if there is one, and to the
+    // user's file otherwise, in the format that file is already in
+    let prefs_path = paths
+        .command_line
+        .as_ref()
+        .or(paths.user.as_ref())
+        .cloned()
+        .unwrap_or(defaults.prefs_path.to_owned());
+    let mut prefs_format = PrefsFormat::from_extension(&prefs_path).unwrap_or_default();
+
+    let file_layers = [
+        (
+            &paths.system,
+            PrefsSource::System as fn(PathBuf) -> PrefsSource,
+        ),
+        (&paths.user, PrefsSource::User),
+        (&paths.project, PrefsSource::Project),
+    ];
+    for (path, source) in file_layers {
+        let Some(path) = path else { continue };
+        if !path.is_file() {
+            debug!("No preferences file at {}", path.display());
+            continue;
+        }
+        let (values, format) = read_prefs_file(path)?;
+        if *path == prefs_path {
+            prefs_format = format;
+        }
+        merge_layer(&mut merged, &mut sources, values, path, |_| {
+            source(path.to_owned())
+        })?;
+    }
+
+    let env_values = env_layer(vars);
+    if !env_values.is_empty() {
+        merge_layer(
+            &mut merged,
+            &mut sources,
+            env_values,
+            Path::new("environment"),
+            |key| PrefsSource::Environment(format!("{ENV_PREFIX}{key}")),
+        )?;
+    }
+
+    if let Some(path) = &paths.command_line {
+        let (values, format) = read_prefs_file(path)?;
+        prefs_format = format;
+        merge_layer(&mut merged, &mut sources, values, path, |_| {
+            PrefsSource::CommandLine(path.to_owned())
+        })?;
+    }
+
+    let mut prefs: Preferences = serde_json::from_value(Value::Object(merged))
+        .map_err(|e| invalid_preferences(&defaults.prefs_path, e))?;
+    prefs.prefs_path = prefs_path;
+    prefs.prefs_format = prefs_format;
+    Ok(LayeredPreferences { prefs, sources })
+}
+
+/// The one preferences file that changes are written to.
+///
+/// Only the keys that are actually in the file are kept and written back, so
+/// saving a change doesn't copy the defaults, or other layers' values, into
+/// the file, where they'd hide the layers below it.
+#[derive(Debug)]
+pub struct WritablePrefs {
+    pub path: PathBuf,
+    pub format: PrefsFormat,
+    values: Map<String, Value>,
+    /// The search dirs all the layers add up to, which changes to the search
+    /// dirs start from when the file doesn't set them
+    effective_search_dirs: Vec<PathBuf>,
+}
+
+impl WritablePrefs {
+    /// The keys in the file, by SCREAMING_SNAKE_CASE name
+    pub fn values(&self) -> &Map<String, Value> {
+        &self.values
+    }
+
+    /// The preferences the file adds up to on its own, on top of the defaults
+    pub fn to_preferences(&self) -> Result<Preferences> {
+        let mut prefs = preferences_from_values(&self.values)
+            .map_err(|e| invalid_preferences(&self.path, e))?;
+        prefs.prefs_path = self.path.to_owned();
+        prefs.prefs_format = self.format;
+        Ok(prefs)
+    }
+
+    /// Set a key in the file, as long as the file still makes valid
+    /// preferences with it
+    fn insert(&mut self, key: &str, value: Value) -> serde_json::Result<()> {
+        let mut values = self.values.clone();
+        values.insert(key.to_string(), value);
+        preferences_from_values(&values)?;
+        self.values = values;
+        Ok(())
+    }
+
+    /// The extra keys set in the file
+    fn extras(&self) -> Map<String, Value> {
+        match self.values.get("EXTRAS") {
+            Some(Value::Object(extras)) => extras.to_owned(),
+            _ => Map::new(),
+        }
+    }
+
+    /// The search dirs set in the file, or the ones from the other layers if
+    /// it doesn't set them
+    fn search_dirs(&self) -> Vec<PathBuf> {
+        self.values
+            .get("RECIPE_SEARCH_DIRS")
+            .and_then(|dirs| serde_json::from_value(dirs.clone()).ok())
+            .unwrap_or_else(|| self.effective_search_dirs.to_owned())
+    }
+
+    fn set_search_dirs(&mut self, dirs: Vec<PathBuf>) -> Result<()> {
+        const KEY: &str = "RECIPE_SEARCH_DIRS";
+        let dirs = serde_json::to_value(dirs).map_err(|e| invalid_value(KEY, e.to_string()))?;
+        self.insert(KEY, dirs)
+            .map_err(|e| invalid_value(KEY, e.to_string()))
+    }
+
+    /// Append a path to the search dirs in the file and write it out.
+    ///
+    /// A file that doesn't set any search dirs yet starts from the ones in
+    /// use, from the other layers or the default, so adding a folder never
+    /// drops the folders that were already searched. The path is stored
+    /// normalized, and a path that's already a search dir, however it's
+    /// spelled, is rejected.
+    pub fn add_to_search_dirs(&mut self, path: &Path) -> Result<()> {
+        let mut dirs = self.search_dirs();
+        add_search_dir(&mut dirs, path)?;
+        self.set_search_dirs(dirs)?;
+        self.write_to_disk()
+    }
+
+    /// Remove a path from the search dirs in the file and write it out,
+    /// returning the entries that were removed.
+    ///
+    /// Every entry that's the same folder is removed, however it's spelled,
+    /// along with its search settings. Like adding, a file that doesn't set
+    /// any search dirs starts from the ones in use. The file is only written
+    /// if something was removed.
+    pub fn remove_from_search_dirs(&mut self, path: &Path) -> Result<Vec<PathBuf>> {
+        let mut dirs = self.search_dirs();
+        let removed = remove_search_dir(&mut dirs, path);
+        let mut changed = !removed.is_empty();
+        if changed {
+            self.set_search_dirs(dirs)?;
+        }
+        if let Some(Value::Object(settings)) = self.values.get_mut("RECIPE_SEARCH_DIR_SETTINGS") {
+            let count = settings.len();
+            settings.retain(|dir, _| !same_search_dir(Path::new(dir), path));
+            changed |= settings.len() != count;
+            if settings.is_empty() {
+                self.values.remove("RECIPE_SEARCH_DIR_SETTINGS");
+            }
+        }
+        if changed {
+            self.write_to_disk()?;
+        }
+        Ok(removed)
+    }
+
+    /// Write the file back out, in the format it was read in
+    pub fn write_to_disk(&self) -> Result<()> {
+        write_prefs_file(&self.path, &self.values, self.format)
+    }
+}
+
+/// Read only the file that preference changes are written to. A file that
+/// doesn't exist yet starts out empty, in the format its name or the
+/// preferences say.
+pub fn read_writable_prefs(prefs: &Preferences) -> Result<WritablePrefs> {
+    let mut writable = WritablePrefs {
+        path: prefs.prefs_path.to_owned(),
+        format: prefs.prefs_format,
+        values: Map::new(),
+        effective_search_dirs: prefs.recipe_search_dirs.to_owned(),
+    };
+    if !writable.path.is_file() {
+        return Ok(writable);
+    }
+    let (values, format) = read_prefs_file(&writable.path)?;
+    preferences_from_values(&values).map_err(|e| invalid_preferences(&writable.path, e))?;
+    writable.values = values;
+    writable.format = format;
+    Ok(writable)
+}
+
+/// Return the value of a preference by its SCREAMING_SNAKE_CASE name, or of
+/// an extra key if no built-in preference has that name
+pub fn get_preference(prefs: &Preferences, key: &str) -> Option<Value> {
+    let mut values = to_values(prefs).ok()?;
+    match values.remove(key) {
+        Some(Value::Null) | None => prefs
+            .extras
+            .as_ref()?
+            .get(key)
+            .and_then(|value| serde_json::to_value(value).ok()),
+        value => value,
+    }
+}
+
+/// Set a preference in the writable file from a string given on the command
+/// line.
+///
+/// A built-in preference reads the string the same way as its AUTOPKG_
+/// environment variable, and the result has to fit the preference. Any other
+/// key is set as an extra key, read as JSON if it's valid JSON, and as a
+/// plain string otherwise.
+pub fn set_preference(prefs: &mut WritablePrefs, key: &str, raw: &str) -> Result<()> {
+    let Some(kind) = preference_kind(key) else {
+        let value = serde_json::from_str(raw)
+            .ok()
+            .filter(|value| *value != PlistDataType::Null)
+            .unwrap_or_else(|| PlistDataType::from(raw));
+        let value = serde_json::to_value(value).map_err(|e| invalid_value(key, e.to_string()))?;
+        let mut extras = prefs.extras();
+        extras.insert(key.to_string(), value);
+        return prefs
+            .insert("EXTRAS", Value::Object(extras))
+            .map_err(|e| invalid_value(key, e.to_string()));
+    };
+    let mut value = kind
+        .parse(raw)
+        .ok_or_else(|| invalid_value(key, format!("{raw} isn't {}", kind.expected())))?;
+    if key == "RECIPE_SEARCH_DIRS" {
+        // Search dirs are stored the same way as with `search-dirs add`
+        let mut dirs = Vec::new();
+        for dir in env::split_paths(raw) {
+            add_search_dir(&mut dirs, &dir)?;
+        }
+        value = serde_json::to_value(dirs).map_err(|e| invalid_value(key, e.to_string()))?;
+    }
+    prefs
+        .insert(key, value)
+        .map_err(|e| invalid_value(key, e.to_string()))
+}
+
+/// Remove a preference from the writable file, so the value from a lower
+/// layer, or the default, applies again. An extra key has to be set to be
+/// removed.
+pub fn unset_preference(prefs: &mut WritablePrefs, key: &str) -> Result<()> {
+    if preference_kind(key).is_some() {
+        prefs.values.remove(key);
+        return Ok(());
+    }
+    let mut extras = prefs.extras();
+    if extras.remove(key).is_none() {
+        return Err(invalid_value(key, "not set".to_string()));
+    }
+    if extras.is_empty() {
+        prefs.values.remove("EXTRAS");
+    } else {
+        prefs
+            .values
+            .insert("EXTRAS".to_string(), Value::Object(extras));
+    }
+    Ok(())
+}
+
+/// Every preference's value by its SCREAMING_SNAKE_CASE name
+fn to_values(prefs: &Preferences) -> Result<Map<String, Value>> {
+    match serde_json::to_value(prefs) {
+        Ok(Value::Object(values)) => Ok(values),
+        Ok(_) => unreachable!("Preferences always serialize to an object"),
+        Err(e) => Err(invalid_preferences(&prefs.prefs_path, e)),
+    }
+}
+
+/// Read preference values on top of the defaults
+fn preferences_from_values(values: &Map<String, Value>) -> serde_json::Result<Preferences> {
+    let mut merged = to_values(&Preferences::new()).unwrap_or_default();
+    merged.extend(values.clone());
+    serde_json::from_value(Value::Object(merged))
+}
+
+/// Append a normalized path to a list of search dirs, unless it's already in
+/// the list, however it's spelled
+pub(crate) fn add_search_dir(dirs: &mut Vec<PathBuf>, path: &Path) -> Result<()> {
+    if let Some(existing) = dirs.iter().find(|dir| same_search_dir(dir, path)) {
+        return Err(invalid_value(
+            "RECIPE_SEARCH_DIRS",
+            format!(
+                "{} is already a search dir, as {}",
+                path.display(),
+                existing.display()
+            ),
+        ));
+    }
+    dirs.push(normalize_search_dir(path));
+    Ok(())
+}
+
+/// Normalize every search dir in a list, leaving out any later spelling of a
+/// folder that's already in it. Returns the search dirs and the entries that
+/// were left out.
+fn dedup_search_dirs(dirs: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let mut kept = Vec::new();
+    let mut duplicates = Vec::new();
+    for dir in dirs {
+        if add_search_dir(&mut kept, &dir).is_err() {
+            duplicates.push(dir);
+        }
+    }
+    (kept, duplicates)
+}
+
+/// Remove every spelling of a path from a list of search dirs, returning the
+/// entries that were removed
+pub(crate) fn remove_search_dir(dirs: &mut Vec<PathBuf>, path: &Path) -> Vec<PathBuf> {
+    debug!("Search dir to remove: {}", path.display());
+    let (removed, kept) = dirs.drain(..).partition(|dir| same_search_dir(dir, path));
+    *dirs = kept;
+    removed
+}
+
+fn invalid_value(key: &str, message: String) -> Error {
+    Error::Preference {
+        key: key.to_string(),
+        message,
+    }
+}
+
+fn invalid_preferences(path: &Path, e: impl Into<ParseError>) -> Error {
+    Error::InvalidPreferences {
+        path: path.to_path_buf(),
+        source: e.into(),
+    }
+}
+
+/// The file formats preferences can be read from and written to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrefsFormat {
+    #[default]
+    Json,
+    XmlPlist,
+    BinaryPlist,
+    Yaml,
+}
+
+impl PrefsFormat {
+    /// Return the format a file name's extension implies, if any
+    pub fn from_extension(path: &Path) -> Option<PrefsFormat> {
+        match path.extension()?.to_str()? {
+            "json" => Some(PrefsFormat::Json),
+            "plist" => Some(PrefsFormat::XmlPlist),
+            "yaml" | "yml" => Some(PrefsFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Work out the format of a preferences file from its contents, falling
+    /// back on its extension when the contents could be more than one.
+    ///
+    /// The contents win over the extension, so a plist called
+    /// `prefs.json` is still read as a plist. Anything that doesn't start
+    /// like a plist or JSON is only YAML if the extension doesn't say
+    /// otherwise.
+    pub fn detect(path: &Path, contents: &[u8]) -> PrefsFormat {
+        if contents.starts_with(b"bplist") {
+            return PrefsFormat::BinaryPlist;
+        }
+        match contents.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'<') => PrefsFormat::XmlPlist,
+            Some(b'{') => PrefsFormat::Json,
+            _ => PrefsFormat::from_extension(path).unwrap_or(PrefsFormat::Yaml),
+        }
+    }
+}
+
+/// Read one preferences file as a dictionary, along with the format it's in
+pub fn read_prefs_file(path: &Path) -> Result<(Map<String, Value>, PrefsFormat)> {
+    debug!("Reading preferences from {}", path.display());
+    let contents = fs::read(path).map_err(|e| Error::io(path, e))?;
+    let format = PrefsFormat::detect(path, &contents);
+    let values = match format {
+        PrefsFormat::Json => {
+            serde_json::from_slice(&contents).map_err(|e| invalid_preferences(path, e))?
+        }
+        // The plist parser reads both kinds of plist
+        PrefsFormat::XmlPlist | PrefsFormat::BinaryPlist => {
+            plist::from_bytes(&contents).map_err(|e| invalid_preferences(path, e))?
+        }
+        PrefsFormat::Yaml => {
+            serde_yaml::from_slice(&contents).map_err(|e| invalid_preferences(path, e))?
+        }
+    };
+    Ok((values, format))
+}
+
+/// Write preferences to a file in the given format
+pub fn write_prefs_file<T: Serialize>(path: &Path, data: &T, format: PrefsFormat) -> Result<()> {
+    let mut output = Vec::new();
+    match format {
+        PrefsFormat::Json => serde_json::to_writer_pretty(&mut output, data)
+            .map_err(|e| invalid_preferences(path, e))?,
+        PrefsFormat::XmlPlist => {
+            plist::to_writer_xml(&mut output, data).map_err(|e| invalid_preferences(path, e))?;
+            output.push(b'\n');
+        }
+        PrefsFormat::BinaryPlist => {
+            plist::to_writer_binary(&mut output, data).map_err(|e| invalid_preferences(path, e))?
+        }
+        PrefsFormat::Yaml => {
+            serde_yaml::to_writer(&mut output, data).map_err(|e| invalid_preferences(path, e))?
+        }
+    }
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+    }
+    fs::write(path, output).map_err(|e| Error::io(path, e))
+}
+
+/// Add one layer on top of the merged preferences, recording where each of
+/// its values came from.
+///
+/// The result is checked straight away, so an invalid value is blamed on
+/// the layer that set it.
+fn merge_layer(
+    merged: &mut Map<String, Value>,
+    sources: &mut BTreeMap<String, PrefsSource>,
+    values: Map<String, Value>,
+    path: &Path,
+    source: impl Fn(&str) -> PrefsSource,
+) -> Result<()> {
+    for (key, value) in values {
+        sources.insert(key.to_owned(), source(&key));
+        merged.insert(key, value);
+    }
+    serde_json::from_value::<Preferences>(Value::Object(merged.clone()))
+        .map(|_| ())
+        .map_err(|e| invalid_preferences(path, e))
+}
+
+/// Turn AUTOPKG_ environment variables into preference values.
+///
+/// Only variables named after a preference in PREFERENCE_KINDS are used.
+/// Variables that can't be read are skipped with a warning.
+fn env_layer(vars: impl IntoIterator<Item = (String, String)>) -> Map<String, Value> {
+    let mut values = Map::new();
+    for (name, raw_value) in vars {
+        let Some((key, kind)) = name
+            .strip_prefix(ENV_PREFIX)
+            .and_then(|key| Some((key, preference_kind(key)?)))
+        else {
+            continue;
+        };
+        let Some(mut value) = kind.parse(&raw_value) else {
+            warn!("Ignoring {name}, which isn't {}", kind.expected());
+            continue;
+        };
+        if key == "RECIPE_SEARCH_DIRS" {
+            let (dirs, duplicates) = dedup_search_dirs(env::split_paths(&raw_value).collect());
+            for duplicate in duplicates {
+                warn!(
+                    "Ignoring {} in {name}, which is already a search dir",
+                    duplicate.display()
+                );
+            }
+            value = Value::from(
+                dirs.iter()
+                    .map(|dir| dir.display().to_string())
+                    .collect::<Vec<_>>(),
+            );
+        }
+        debug!("Using {name} from the environment");
+        values.insert(key.to_string(), value);
+    }
+    values
+}
+
+/// What happened to each key of an imported Python AutoPkg preferences file
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Keys that set a preference
+    pub imported: Vec<String>,
+    /// Keys with no matching preference, which were added to the extras
+    pub extras: Vec<String>,
+    /// Keys that couldn't be translated, along with why
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Copy the settings from a Python AutoPkg preferences file into the
+/// writable preferences file.
+///
+/// Keys that match a preference replace its value, and any other key is added
+/// to the extras, as long as it has a value. Python
+/// AutoPkg's RECIPE_OVERRIDE_DIRS becomes RECIPE_OVERRIDE_DIR, which only
+/// holds the first of them, and a leading ~ in a path is expanded the way
+/// Python AutoPkg does. Search dirs are normalized the same way as with
+/// `search-dirs add`, and only the first spelling of each folder is kept.
+pub fn import_python_prefs(
+    prefs: &mut WritablePrefs,
+    values: Map<String, Value>,
+) -> Result<ImportReport> {
+    let mut report = ImportReport::default();
+    let mut extras = prefs.extras();
+    for (key, value) in values {
+        let (name, value) = match (key.as_str(), value) {
+            ("RECIPE_SEARCH_DIRS", value) => {
+                let dirs = match value {
+                    Value::String(dir) => vec![PathBuf::from(dir)],
+                    value => match serde_json::from_value(value) {
+                        Ok(dirs) => dirs,
+                        Err(e) => {
+                            report.skipped.push((key, e.to_string()));
+                            continue;
+                        }
+                    },
+                };
+                let (dirs, duplicates) = dedup_search_dirs(dirs);
+                if !duplicates.is_empty() {
+                    let duplicates: Vec<String> = duplicates
+                        .iter()
+                        .map(|dir| dir.display().to_string())
+                        .collect();
+                    report.skipped.push((
+                        key.to_owned(),
+                        format!("{} already listed, so left out", duplicates.join(", ")),
+                    ));
+                }
+                let dirs: Vec<String> = dirs.iter().map(|dir| dir.display().to_string()).collect();
+                ("RECIPE_SEARCH_DIRS", Value::from(dirs))
+            }
+            ("RECIPE_OVERRIDE_DIRS", Value::String(dir)) => {
+                ("RECIPE_OVERRIDE_DIR", Value::String(dir))
+            }
+            ("RECIPE_OVERRIDE_DIRS", Value::Array(dirs)) => {
+                let mut dirs = dirs.into_iter();
+                let Some(first) = dirs.next() else {
+                    report
+                        .skipped
+                        .push((key, "there are no override dirs in it".to_string()));
+                    continue;
+                };
+                let rest: Vec<String> = dirs
+                    .map(|dir| dir.as_str().map_or(dir.to_string(), str::to_string))
+                    .collect();
+                if !rest.is_empty() {
+                    report.skipped.push((
+                        key.to_owned(),
+                        format!(
+                            "only one override dir can be used, so {} left out",
+                            rest.join(", ")
+                        ),
+                    ));
+                }
+                ("RECIPE_OVERRIDE_DIR", first)
+            }
+            (name, value) if preference_kind(name).is_some() => (name, value),
+            (_, Value::Null) => {
+                report.skipped.push((key, "it has no value".to_string()));
+                continue;
+            }
+            (_, value) => {
+                // Extras have to be something a plist can hold
+                match serde_json::from_value::<PlistDataType>(value.clone()) {
+                    Ok(_) => {
+                        extras.insert(key.to_owned(), value);
+                        report.extras.push(key);
+                    }
+                    Err(e) => report.skipped.push((key, e.to_string())),
+                }
+                continue;
+            }
+        };
+        match prefs.insert(name, expand_home(value)) {
+            Ok(()) => report.imported.push(key),
+            Err(e) => report.skipped.push((key, e.to_string())),
+        }
+    }
+
+    if !extras.is_empty() {
+        prefs
+            .insert("EXTRAS", Value::Object(extras))
+            .map_err(|e| invalid_preferences(&prefs.path, e))?;
+    }
+    Ok(report)
+}
+
+/// Expand a leading ~ in a path, or in each path of a list, to the home folder
+fn expand_home(value: Value) -> Value {
+    match value {
+        Value::String(path) => {
+            Value::String(expand_home_dir(Path::new(&path)).display().to_string())
+        }
+        Value::Array(paths) => Value::Array(paths.into_iter().map(expand_home).collect()),
+        value => value,
+    }
+}
+
+/// Expand a leading ~ in a path to the home folder
+pub fn expand_home_dir(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) if rest.as_os_str().is_empty() => home,
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Spell a search dir the way it's stored in the preferences: with ~
+/// expanded, relative to the current folder if it's relative, and without
+/// any `.` or `..` parts or trailing slash
+pub fn normalize_search_dir(path: &Path) -> PathBuf {
+    let path = expand_home_dir(path);
+    let path = match env::current_dir() {
+        Ok(current_dir) if path.is_relative() => current_dir.join(path),
+        _ => path,
+    };
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Whether two spellings of a search dir are the same folder, following any
+/// symlinks if the folder exists
+pub fn same_search_dir(a: &Path, b: &Path) -> bool {
+    let resolve = |path: &Path| {
+        let normalized = normalize_search_dir(path);
+        normalized.canonicalize().unwrap_or(normalized)
+    };
+    resolve(a) == resolve(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SearchDirSettings;
+
+    /// Write a preferences file and return its path
+    fn write_prefs(dir: &Path, name: &str, json: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, json).unwrap();
+        path
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_layers_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = PrefsPaths {
+            system: Some(write_prefs(
+                dir.path(),
+                "system.json",
+                r#"{"RECIPE_SEARCH_DIRS": ["/system"], "CACHE_DIR": "/system/Cache",
+                    "MUNKI_REPO": "/system/munki"}"#,
+            )),
+            user: Some(write_prefs(
+                dir.path(),
+                "user.json",
+                r#"{"RECIPE_SEARCH_DIRS": ["/user"], "CACHE_DIR": "/user/Cache"}"#,
+            )),
+            project: Some(dir.path().join("missing.json")),
+            command_line: None,
+        };
+        let layered = load_layered_preferences(
+            &paths,
+            vars(&[
+                ("AUTOPKG_CACHE_DIR", "/ci/Cache"),
+                ("AUTOPKG_DISABLE_CODE_SIGNATURE_VERIFICATION", "yes"),
+                ("AUTOPKG_NOT_A_PREFERENCE", "ignored"),
+                ("CACHE_DIR", "ignored"),
+            ]),
+        )
+        .unwrap();
+        let prefs = &layered.prefs;
+        assert_eq!(prefs.recipe_search_dirs, [PathBuf::from("/user")]);
+        assert_eq!(prefs.cache_dir, PathBuf::from("/ci/Cache"));
+        assert_eq!(prefs.munki_repo, Some(PathBuf::from("/system/munki")));
+        assert!(prefs.disable_code_signature_verification);
+        assert_eq!(&prefs.prefs_path, paths.user.as_ref().unwrap());
+
+        assert_eq!(
+            layered.source("RECIPE_SEARCH_DIRS"),
+            &PrefsSource::User(paths.user.clone().unwrap())
+        );
+        assert_eq!(
+            layered.source("CACHE_DIR"),
+            &PrefsSource::Environment("AUTOPKG_CACHE_DIR".to_string())
+        );
+        assert_eq!(
+            layered.source("MUNKI_REPO"),
+            &PrefsSource::System(paths.system.clone().unwrap())
+        );
+        assert_eq!(layered.source("RECIPE_MAP_PATH"), &PrefsSource::Default);
+    }
+
+    #[test]
+    fn test_command_line_prefs_win() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = PrefsPaths {
+            project: Some(write_prefs(
+                dir.path(),
+                PROJECT_PREFERENCES_FILENAME,
+                r#"{"RECIPE_SEARCH_DIRS": ["/project"]}"#,
+            )),
+            command_line: Some(write_prefs(
+                dir.path(),
+                "cli.json",
+                r#"{"RECIPE_SEARCH_DIRS": ["/cli"]}"#,
+            )),
+            ..Default::default()
+        };
+        let layered = load_layered_preferences(
+            &paths,
+            vars(&[(
+                "AUTOPKG_RECIPE_SEARCH_DIRS",
+                env::join_paths(["/env/a", "/env/b"])
+                    .unwrap()
+                    .to_str()
+                    .unwrap(),
+            )]),
+        )
+        .unwrap();
+        assert_eq!(layered.prefs.recipe_search_dirs, [PathBuf::from("/cli")]);
+        assert_eq!(
+            &layered.prefs.prefs_path,
+            paths.command_line.as_ref().unwrap()
+        );
+
+        // Without --prefs, the environment beats the project file
+        let paths = PrefsPaths {
+            command_line: None,
+            ..paths
+        };
+        let layered = load_layered_preferences(
+            &paths,
+            vars(&[(
+                "AUTOPKG_RECIPE_SEARCH_DIRS",
+                env::join_paths(["/env/a", "/env/b"])
+                    .unwrap()
+                    .to_str()
+                    .unwrap(),
+            )]),
+        )
+        .unwrap();
+        assert_eq!(
+            layered.prefs.recipe_search_dirs,
+            [PathBuf::from("/env/a"), PathBuf::from("/env/b")]
+        );
+        assert_eq!(
+            find_project_prefs(&dir.path().join("nested/folder")),
+            paths.project
+        );
+    }
+
+    #[test]
+    fn test_prefs_round_trip_in_every_format() {
+        let dir = tempfile::tempdir().unwrap();
+        for (file_name, format) in [
+            ("prefs.json", PrefsFormat::Json),
+            ("prefs.plist", PrefsFormat::XmlPlist),
+            ("binary.plist", PrefsFormat::BinaryPlist),
+            ("prefs.yaml", PrefsFormat::Yaml),
+            // Files without a known extension are detected by their contents
+            ("prefs", PrefsFormat::XmlPlist),
+        ] {
+            let path = dir.path().join(file_name);
+            let mut prefs = Preferences::new();
+            prefs.recipe_search_dirs = vec![PathBuf::from("/Recipes")];
+            prefs.munki_repo = Some(PathBuf::from("/munki"));
+            prefs.prefs_path = path.to_owned();
+            prefs.prefs_format = format;
+            prefs.write_to_disk().unwrap();
+            if format == PrefsFormat::BinaryPlist {
+                assert!(fs::read(&path).unwrap().starts_with(b"bplist"));
+            }
+
+            let read = Preferences::new().read_from_disk(&path).unwrap();
+            assert_eq!(read.prefs_format, format, "{file_name}");
+            assert_eq!(read.munki_repo, prefs.munki_repo);
+            // And it's written back the same way
+            let mut writable = read_writable_prefs(&read).unwrap();
+            writable.add_to_search_dirs(Path::new("/More")).unwrap();
+            let (values, written_format) = read_prefs_file(&path).unwrap();
+            assert_eq!(written_format, format);
+            assert_eq!(
+                values["RECIPE_SEARCH_DIRS"],
+                serde_json::json!(["/Recipes", "/More"])
+            );
+        }
+    }
+
+    #[test]
+    fn test_detect_prefs_format() {
+        let path = Path::new("com.github.autopkg");
+        assert_eq!(PrefsFormat::detect(path, b"  {}"), PrefsFormat::Json);
+        assert_eq!(
+            PrefsFormat::detect(path, b"<?xml version=\"1.0\"?>"),
+            PrefsFormat::XmlPlist
+        );
+        assert_eq!(
+            PrefsFormat::detect(path, b"CACHE_DIR: /tmp"),
+            PrefsFormat::Yaml
+        );
+        assert_eq!(
+            PrefsFormat::detect(Path::new("prefs.json"), b"bplist00"),
+            PrefsFormat::BinaryPlist
+        );
+        // The contents win over the extension, which is only used when the
+        // contents could be more than one format
+        assert_eq!(
+            PrefsFormat::detect(Path::new("prefs.json"), b"\n<?xml version=\"1.0\"?>"),
+            PrefsFormat::XmlPlist
+        );
+        assert_eq!(
+            PrefsFormat::detect(Path::new("prefs.plist"), b"{}"),
+            PrefsFormat::Json
+        );
+        assert_eq!(
+            PrefsFormat::detect(Path::new("prefs.json"), b""),
+            PrefsFormat::Json
+        );
+    }
+
+    #[test]
+    fn test_invalid_layer_is_blamed() {
+        let dir = tempfile::tempdir().unwrap();
+        let user = write_prefs(
+            dir.path(),
+            "user.json",
+            r#"{"DISABLE_CODE_SIGNATURE_VERIFICATION": "sometimes"}"#,
+        );
+        let paths = PrefsPaths {
+            user: Some(user.to_owned()),
+            ..Default::default()
+        };
+        let err = load_layered_preferences(&paths, vars(&[])).unwrap_err();
+        assert!(matches!(err, Error::InvalidPreferences { path, .. } if path == user));
+    }
+
+    #[test]
+    fn test_extras_hold_any_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = PrefsPaths {
+            user: Some(write_prefs(
+                dir.path(),
+                "user.json",
+                r#"{"RECIPE_SEARCH_DIRS": ["."], "EXTRAS": {
+                    "FAIL_RECIPES_WITHOUT_TRUST_INFO": true,
+                    "CURL_RETRIES": 3,
+                    "MUNKI_REPO_PLUGIN_OPTIONS": ["--verbose", "--quiet"],
+                    "CACHE_DIR": "/not/the/cache"}}"#,
+            )),
+            ..Default::default()
+        };
+        let prefs = load_layered_preferences(&paths, vars(&[])).unwrap().prefs;
+
+        let env = prefs.to_environment();
+        assert_eq!(
+            env["FAIL_RECIPES_WITHOUT_TRUST_INFO"],
+            PlistDataType::Bool(true)
+        );
+        assert_eq!(env["CURL_RETRIES"], PlistDataType::Integer(3.into()));
+        assert_eq!(
+            env["MUNKI_REPO_PLUGIN_OPTIONS"],
+            PlistDataType::from(vec!["--verbose".to_string(), "--quiet".to_string()])
+        );
+        // A built-in preference always wins over an extra key
+        assert_eq!(
+            env["CACHE_DIR"],
+            PlistDataType::from(prefs.cache_dir.display().to_string())
+        );
+
+        let display = prefs.to_string();
+        assert!(display.contains("    CURL_RETRIES: 3\n"));
+        assert!(display.contains("    MUNKI_REPO_PLUGIN_OPTIONS:\n        - --verbose\n"));
+    }
+
+    #[test]
+    fn test_get_set_and_unset_preferences() {
+        let dir = tempfile::tempdir().unwrap();
+        // Only the writable file is read, so a partial file is fine
+        let path = write_prefs(dir.path(), "prefs.yaml", "CACHE_DIR: /Cache\n");
+        let layered = Preferences {
+            prefs_path: path.to_owned(),
+            recipe_repo_dir: PathBuf::from("/from/another/layer"),
+            ..Preferences::new()
+        };
+        let mut prefs = read_writable_prefs(&layered).unwrap();
+        assert_eq!(prefs.format, PrefsFormat::Yaml);
+        assert_eq!(prefs.values().keys().collect::<Vec<_>>(), ["CACHE_DIR"]);
+
+        set_preference(&mut prefs, "DISABLE_CODE_SIGNATURE_VERIFICATION", "yes").unwrap();
+        set_preference(&mut prefs, "MUNKI_REPO", "/munki").unwrap();
+        set_preference(&mut prefs, "CURL_RETRIES", "3").unwrap();
+        set_preference(&mut prefs, "MUNKI_REPO_PLUGIN", "FileRepo").unwrap();
+        let read = prefs.to_preferences().unwrap();
+        assert!(read.disable_code_signature_verification);
+        assert_eq!(
+            get_preference(&read, "MUNKI_REPO"),
+            Some(Value::from("/munki"))
+        );
+        assert_eq!(get_preference(&read, "CURL_RETRIES"), Some(Value::from(3)));
+        assert_eq!(
+            get_preference(&read, "MUNKI_REPO_PLUGIN"),
+            Some(Value::from("FileRepo"))
+        );
+        let err =
+            set_preference(&mut prefs, "DISABLE_CODE_SIGNATURE_VERIFICATION", "maybe").unwrap_err();
+        assert!(matches!(err, Error::Preference { .. }));
+        let err =
+            set_preference(&mut prefs, "RECIPE_SEARCH_DIR_SETTINGS", r#"{"/a": 1}"#).unwrap_err();
+        assert!(matches!(err, Error::Preference { .. }));
+
+        unset_preference(&mut prefs, "MUNKI_REPO").unwrap();
+        unset_preference(&mut prefs, "CACHE_DIR").unwrap();
+        unset_preference(&mut prefs, "CURL_RETRIES").unwrap();
+        assert!(unset_preference(&mut prefs, "CURL_RETRIES").is_err());
+
+        // Only the keys that were set are written
+        prefs.write_to_disk().unwrap();
+        let (values, format) = read_prefs_file(&path).unwrap();
+        assert_eq!(format, PrefsFormat::Yaml);
+        assert_eq!(
+            Value::Object(values),
+            serde_json::json!({
+                "DISABLE_CODE_SIGNATURE_VERIFICATION": true,
+                "EXTRAS": {"MUNKI_REPO_PLUGIN": "FileRepo"},
+            })
+        );
+    }
+
+    #[test]
+    fn test_lower_layers_survive_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let system = write_prefs(
+            dir.path(),
+            "system.json",
+            r#"{"CACHE_DIR": "/SystemCache", "MUNKI_REPO": "/system/munki"}"#,
+        );
+        let paths = PrefsPaths {
+            system: Some(system),
+            user: Some(dir.path().join("user.json")),
+            ..Default::default()
+        };
+        let load = || load_layered_preferences(&paths, Vec::new()).unwrap().prefs;
+
+        // Setting one preference in a user file that doesn't exist yet only
+        // writes that preference
+        let mut writable = read_writable_prefs(&load()).unwrap();
+        set_preference(&mut writable, "MUNKI_REPO", "/munki").unwrap();
+        writable.write_to_disk().unwrap();
+        let (values, _) = read_prefs_file(paths.user.as_ref().unwrap()).unwrap();
+        assert_eq!(
+            Value::Object(values),
+            serde_json::json!({"MUNKI_REPO": "/munki"})
+        );
+        let prefs = load();
+        assert_eq!(prefs.cache_dir, PathBuf::from("/SystemCache"));
+        assert_eq!(prefs.munki_repo, Some(PathBuf::from("/munki")));
+
+        // And unsetting it lets the system value apply again
+        let mut writable = read_writable_prefs(&prefs).unwrap();
+        unset_preference(&mut writable, "MUNKI_REPO").unwrap();
+        writable.write_to_disk().unwrap();
+        assert_eq!(load().munki_repo, Some(PathBuf::from("/system/munki")));
+
+        assert_eq!(load().cache_dir, PathBuf::from("/SystemCache"));
+    }
+
+    #[test]
+    fn test_search_dir_changes_start_from_lower_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let system = write_prefs(
+            dir.path(),
+            "system.json",
+            r#"{"RECIPE_SEARCH_DIRS": ["/SystemRecipes", "/Shared/Recipes"]}"#,
+        );
+        let user = dir.path().join("user.json");
+        let paths = PrefsPaths {
+            system: Some(system),
+            user: Some(user.to_owned()),
+            ..Default::default()
+        };
+        let load = || load_layered_preferences(&paths, Vec::new()).unwrap().prefs;
+
+        // Adding to a user file that doesn't set any search dirs keeps the
+        // system's, instead of replacing them with only the new one
+        let mut writable = read_writable_prefs(&load()).unwrap();
+        writable.add_to_search_dirs(Path::new("/Recipes")).unwrap();
+        assert_eq!(
+            load().recipe_search_dirs,
+            ["/SystemRecipes", "/Shared/Recipes", "/Recipes"].map(PathBuf::from)
+        );
+        // And a search dir from the system can't be added twice
+        let mut writable = read_writable_prefs(&load()).unwrap();
+        assert!(writable
+            .add_to_search_dirs(Path::new("/SystemRecipes/"))
+            .is_err());
+
+        // Removing one of the system's search dirs works the same way
+        fs::remove_file(&user).unwrap();
+        let mut writable = read_writable_prefs(&load()).unwrap();
+        let removed = writable
+            .remove_from_search_dirs(Path::new("/SystemRecipes"))
+            .unwrap();
+        assert_eq!(removed, [PathBuf::from("/SystemRecipes")]);
+        let (values, _) = read_prefs_file(&user).unwrap();
+        assert_eq!(
+            Value::Object(values),
+            serde_json::json!({"RECIPE_SEARCH_DIRS": ["/Shared/Recipes"]})
+        );
+    }
+
+    #[test]
+    fn test_search_dirs_are_normalized() {
+        let home = dirs::home_dir().unwrap();
+        let current_dir = env::current_dir().unwrap();
+        assert_eq!(
+            normalize_search_dir(Path::new("~/Recipes/")),
+            home.join("Recipes")
+        );
+        assert_eq!(
+            normalize_search_dir(Path::new("./Recipes/../Other/./Recipes")),
+            current_dir.join("Other/Recipes")
+        );
+        assert!(same_search_dir(
+            Path::new("Recipes"),
+            &current_dir.join("Recipes/")
+        ));
+        assert!(!same_search_dir(
+            Path::new("Recipes"),
+            Path::new("~/Recipes")
+        ));
+    }
+
+    #[test]
+    fn test_add_and_remove_search_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let recipes = dir.path().join("Recipes");
+        fs::create_dir(&recipes).unwrap();
+        // Two spellings of the same folder can only get in by editing the
+        // file by hand
+        let path = write_prefs(
+            dir.path(),
+            "prefs.json",
+            &serde_json::json!({
+                "RECIPE_SEARCH_DIRS": [recipes, dir.path().join("Recipes/"), dir.path().join("Other")],
+            })
+            .to_string(),
+        );
+        let prefs = Preferences::new().read_from_disk(&path).unwrap();
+        let mut writable = read_writable_prefs(&prefs).unwrap();
+        let search_dirs =
+            |writable: &WritablePrefs| writable.to_preferences().unwrap().recipe_search_dirs;
+
+        let err = writable
+            .add_to_search_dirs(&dir.path().join("Other/../Recipes"))
+            .unwrap_err();
+        assert!(matches!(err, Error::Preference { .. }));
+        writable
+            .add_to_search_dirs(&dir.path().join("More/./Recipes/"))
+            .unwrap();
+        assert_eq!(
+            search_dirs(&writable).last(),
+            Some(&dir.path().join("More/Recipes"))
+        );
+
+        // Every spelling of the same folder is removed
+        let removed = writable
+            .remove_from_search_dirs(&dir.path().join("Other/../Recipes/"))
+            .unwrap();
+        assert_eq!(removed.len(), 2);
+        assert_eq!(
+            search_dirs(&writable),
+            [dir.path().join("Other"), dir.path().join("More/Recipes")]
+        );
+        assert!(writable
+            .remove_from_search_dirs(Path::new("/not/a/search/dir"))
+            .unwrap()
+            .is_empty());
+        let read = Preferences::new().read_from_disk(&path).unwrap();
+        assert_eq!(read.recipe_search_dirs, search_dirs(&writable));
+    }
+
+    #[test]
+    fn test_search_dirs_are_normalized_wherever_they_are_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let home = dirs::home_dir().unwrap();
+        let current = Preferences {
+            prefs_path: dir.path().join("prefs.json"),
+            ..Preferences::new()
+        };
+        let joined = |dirs: &[&str]| env::join_paths(dirs).unwrap().into_string().unwrap();
+
+        // Setting them on the command line rejects a folder that's listed twice
+        let mut writable = read_writable_prefs(&current).unwrap();
+        set_preference(
+            &mut writable,
+            "RECIPE_SEARCH_DIRS",
+            &joined(&["~/Recipes/", "/Other/./Recipes"]),
+        )
+        .unwrap();
+        assert_eq!(
+            writable.to_preferences().unwrap().recipe_search_dirs,
+            [home.join("Recipes"), PathBuf::from("/Other/Recipes")]
+        );
+        let err = set_preference(
+            &mut writable,
+            "RECIPE_SEARCH_DIRS",
+            &joined(&["/Recipes", "/Recipes/"]),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::Preference { .. }));
+
+        // The environment and imported Python preferences keep only the
+        // first spelling of each folder
+        let values = env_layer([(
+            "AUTOPKG_RECIPE_SEARCH_DIRS".to_string(),
+            joined(&["~/Recipes", "/Other", "~/Recipes/"]),
+        )]);
+        assert_eq!(
+            values["RECIPE_SEARCH_DIRS"],
+            serde_json::json!([home.join("Recipes"), "/Other"])
+        );
+        let mut writable = read_writable_prefs(&current).unwrap();
+        let report = import_python_prefs(
+            &mut writable,
+            serde_json::json!({"RECIPE_SEARCH_DIRS": ["/Recipes", "/Recipes/../Recipes"]})
+                .as_object()
+                .unwrap()
+                .to_owned(),
+        )
+        .unwrap();
+        assert_eq!(report.imported, ["RECIPE_SEARCH_DIRS"]);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(
+            writable.to_preferences().unwrap().recipe_search_dirs,
+            [PathBuf::from("/Recipes")]
+        );
+    }
+
+    #[test]
+    fn test_search_dir_settings_follow_any_spelling() {
+        let dir = tempfile::tempdir().unwrap();
+        let recipes = dirs::home_dir().unwrap().join("AutoPkgTestRecipes");
+        let path = write_prefs(
+            dir.path(),
+            "prefs.json",
+            &serde_json::json!({
+                "RECIPE_SEARCH_DIRS": [recipes, "/Other"],
+                "RECIPE_SEARCH_DIR_SETTINGS": {"~/AutoPkgTestRecipes/": {"MAX_DEPTH": 5}},
+            })
+            .to_string(),
+        );
+        let prefs = Preferences::new().read_from_disk(&path).unwrap();
+        assert_eq!(prefs.search_dir_settings(&recipes).max_depth, 5);
+        assert_eq!(
+            prefs.search_dir_settings(Path::new("/Other")),
+            SearchDirSettings::default()
+        );
+
+        // Removing the search dir removes its settings too
+        let mut writable = read_writable_prefs(&prefs).unwrap();
+        let removed = writable
+            .remove_from_search_dirs(Path::new("~/AutoPkgTestRecipes"))
+            .unwrap();
+        assert_eq!(removed, [recipes]);
+        let (values, _) = read_prefs_file(&path).unwrap();
+        assert_eq!(
+            Value::Object(values),
+            serde_json::json!({"RECIPE_SEARCH_DIRS": ["/Other"]})
+        );
+    }
+
+    #[test]
+    fn test_import_python_prefs() {
+        let dir = tempfile::tempdir().unwrap();
+        let python = write_prefs(
+            dir.path(),
+            "com.github.autopkg.plist",
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+    <key>RECIPE_SEARCH_DIRS</key>
+    <array><string>.</string><string>~/Library/AutoPkg/Recipes</string></array>
+    <key>RECIPE_OVERRIDE_DIRS</key>
+    <array><string>/Overrides</string><string>/Shared/Overrides</string></array>
+    <key>CACHE_DIR</key>
+    <string>/Cache</string>
+    <key>DISABLE_CODE_SIGNATURE_VERIFICATION</key>
+    <string>sometimes</string>
+    <key>FAIL_RECIPES_WITHOUT_TRUST_INFO</key>
+    <true/>
+    <key>MUNKI_REPO_PLUGIN</key>
+    <string>FileRepo</string>
+    <key>MUNKI_REPO_PLUGIN_OPTIONS</key>
+    <array><string>--verbose</string></array>
+    <key>RECIPE_REPOS</key>
+    <dict>
+        <key>/RecipeRepos/com.github.autopkg.recipes</key>
+        <dict><key>URL</key><string>https://github.com/autopkg/recipes</string></dict>
+    </dict>
+</dict>
+</plist>
+"#,
+        );
+        let current = Preferences {
+            prefs_path: write_prefs(dir.path(), "prefs.yaml", "MUNKI_REPO: /munki\n"),
+            ..Preferences::new()
+        };
+        let mut writable = read_writable_prefs(&current).unwrap();
+        let (values, _) = read_prefs_file(&python).unwrap();
+        let report = import_python_prefs(&mut writable, values).unwrap();
+
+        assert_eq!(
+            report.imported,
+            ["CACHE_DIR", "RECIPE_OVERRIDE_DIRS", "RECIPE_SEARCH_DIRS"]
+        );
+        assert_eq!(
+            report.extras,
+            [
+                "FAIL_RECIPES_WITHOUT_TRUST_INFO",
+                "MUNKI_REPO_PLUGIN",
+                "MUNKI_REPO_PLUGIN_OPTIONS",
+                "RECIPE_REPOS"
+            ]
+        );
+        let skipped: Vec<&str> = report.skipped.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(
+            skipped,
+            [
+                "DISABLE_CODE_SIGNATURE_VERIFICATION",
+                "RECIPE_OVERRIDE_DIRS"
+            ]
+        );
+
+        let prefs = writable.to_preferences().unwrap();
+        let home = dirs::home_dir().unwrap();
+        // Search dirs are stored normalized, like with `search-dirs add`
+        assert_eq!(
+            prefs.recipe_search_dirs,
+            [
+                env::current_dir().unwrap(),
+                home.join("Library/AutoPkg/Recipes")
+            ]
+        );
+        assert_eq!(prefs.recipe_override_dir, PathBuf::from("/Overrides"));
+        assert_eq!(prefs.cache_dir, PathBuf::from("/Cache"));
+        assert!(!prefs.disable_code_signature_verification);
+        // Anything the Python preferences didn't set is left alone
+        assert_eq!(prefs.munki_repo, Some(PathBuf::from("/munki")));
+        assert_eq!(prefs.prefs_format, PrefsFormat::Yaml);
+        let extras = prefs.extras.as_ref().unwrap();
+        assert_eq!(
+            extras["FAIL_RECIPES_WITHOUT_TRUST_INFO"],
+            PlistDataType::Bool(true)
+        );
+        assert_eq!(extras["MUNKI_REPO_PLUGIN"], PlistDataType::from("FileRepo"));
+        assert_eq!(
+            extras["MUNKI_REPO_PLUGIN_OPTIONS"],
+            PlistDataType::from(vec!["--verbose".to_string()])
+        );
+
+        // And only what was imported is added to the file
+        writable.write_to_disk().unwrap();
+        let (values, _) = read_prefs_file(&writable.path).unwrap();
+        assert_eq!(
+            values.keys().collect::<Vec<_>>(),
+            [
+                "CACHE_DIR",
+                "EXTRAS",
+                "MUNKI_REPO",
+                "RECIPE_OVERRIDE_DIR",
+                "RECIPE_SEARCH_DIRS"
+            ]
+        );
+        let read = Preferences::new().read_from_disk(&writable.path).unwrap();
+        assert_eq!(read.recipe_override_dir, PathBuf::from("/Overrides"));
+        assert_eq!(read.extras, prefs.extras);
+    }
+}
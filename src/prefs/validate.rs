@@ -0,0 +1,452 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::{fs, io, process};
+
+use super::{load_layered_preferences, preference_kind, to_values, PrefsFormat, PrefsPaths};
+use crate::Preferences;
+
+/// How serious a problem with the preferences is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// AutoPkg will run, but probably not the way it was meant to
+    Warning,
+    /// AutoPkg can't run, or will fail as soon as it uses the preference
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// One problem found with the preferences
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub severity: Severity,
+    /// The preferences file the problem is in, if it's in a file
+    pub path: Option<PathBuf>,
+    /// Line and column of a parse error, counting from 1, if the parser
+    /// knows them
+    pub position: Option<(usize, usize)>,
+    /// The preference the problem is with, if it's with one
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl Problem {
+    fn new(severity: Severity, key: &str, message: String) -> Problem {
+        Problem {
+            severity,
+            path: None,
+            position: None,
+            key: Some(key.to_string()),
+            message,
+        }
+    }
+
+    fn in_file(mut self, path: &Path) -> Problem {
+        self.path = Some(path.to_path_buf());
+        self
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.severity)?;
+        if let Some(path) = &self.path {
+            write!(f, "{}", path.display())?;
+            if let Some((line, column)) = self.position {
+                write!(f, ":{line}:{column}")?;
+            }
+            write!(f, ": ")?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, "{key}: ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Check every layer of preferences, then the preferences they add up to.
+///
+/// The preferences themselves are only checked if every file could be read,
+/// since they can't be loaded otherwise.
+pub fn diagnose(
+    paths: &PrefsPaths,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Vec<Problem> {
+    let mut problems: Vec<Problem> = [&paths.system, &paths.user, &paths.project]
+        .into_iter()
+        .flatten()
+        .filter(|path| path.is_file())
+        .chain(&paths.command_line)
+        .flat_map(|path| check_prefs_file(path))
+        .collect();
+    if problems.iter().any(|p| p.severity == Severity::Error) {
+        return problems;
+    }
+    match load_layered_preferences(paths, vars) {
+        Ok(layered) => problems.extend(check_preferences(&layered.prefs)),
+        Err(e) => problems.push(Problem {
+            severity: Severity::Error,
+            path: None,
+            position: None,
+            key: None,
+            message: e.to_string(),
+        }),
+    }
+    problems
+}
+
+/// Check that a preferences file can be read, and that each of its values
+/// fits the preference it sets
+pub fn check_prefs_file(path: &Path) -> Vec<Problem> {
+    let error = |position, message| Problem {
+        severity: Severity::Error,
+        path: Some(path.to_path_buf()),
+        position,
+        key: None,
+        message,
+    };
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) => return vec![error(None, e.to_string())],
+    };
+    let values: Map<String, Value> = match PrefsFormat::detect(path, &contents) {
+        PrefsFormat::Json => match serde_json::from_slice(&contents) {
+            Ok(values) => values,
+            Err(e) => {
+                let position = (e.line() > 0).then(|| (e.line(), e.column()));
+                return vec![error(position, e.to_string())];
+            }
+        },
+        PrefsFormat::Yaml => match serde_yaml::from_slice(&contents) {
+            Ok(values) => values,
+            Err(e) => {
+                let position = e.location().map(|l| (l.line(), l.column()));
+                return vec![error(position, e.to_string())];
+            }
+        },
+        PrefsFormat::XmlPlist | PrefsFormat::BinaryPlist => match plist::from_bytes(&contents) {
+            Ok(values) => values,
+            Err(e) => return vec![error(None, e.to_string())],
+        },
+    };
+
+    // Each value is checked on its own, on top of the defaults, so every bad
+    // value is found instead of just the first one
+    let Ok(defaults) = to_values(&Preferences::new()) else {
+        return Vec::new();
+    };
+    let mut problems = Vec::new();
+    for (key, value) in values {
+        if preference_kind(&key).is_none() {
+            problems.push(
+                Problem::new(
+                    Severity::Warning,
+                    &key,
+                    "not a preference, so it's ignored. Extra keys go in EXTRAS".to_string(),
+                )
+                .in_file(path),
+            );
+            continue;
+        }
+        let mut merged = defaults.clone();
+        merged.insert(key.to_owned(), value);
+        if let Err(e) = serde_json::from_value::<Preferences>(Value::Object(merged)) {
+            problems.push(Problem::new(Severity::Error, &key, e.to_string()).in_file(path));
+        }
+    }
+    problems
+}
+
+/// Check that the folders and files the preferences point at are usable
+pub fn check_preferences(prefs: &Preferences) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    let mut seen = BTreeMap::new();
+    for dir in &prefs.recipe_search_dirs {
+        let resolved = dir.canonicalize().unwrap_or_else(|_| dir.to_owned());
+        if let Some(first) = seen.insert(resolved, dir) {
+            problems.push(Problem::new(
+                Severity::Warning,
+                "RECIPE_SEARCH_DIRS",
+                format!(
+                    "{} is listed more than once (as {})",
+                    dir.display(),
+                    first.display()
+                ),
+            ));
+        } else if !dir.is_dir() {
+            problems.push(Problem::new(
+                Severity::Warning,
+                "RECIPE_SEARCH_DIRS",
+                format!("{} doesn't exist, so it's skipped", dir.display()),
+            ));
+        }
+    }
+
+    for (key, dir) in [
+        ("CACHE_DIR", &prefs.cache_dir),
+        ("RECIPE_OVERRIDE_DIR", &prefs.recipe_override_dir),
+        ("RECIPE_REPO_DIR", &prefs.recipe_repo_dir),
+    ] {
+        if let Some(message) = check_creatable_dir(dir) {
+            problems.push(Problem::new(Severity::Error, key, message));
+        }
+    }
+    if let Some(message) = prefs
+        .recipe_map_path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .and_then(check_creatable_dir)
+    {
+        problems.push(Problem::new(Severity::Error, "RECIPE_MAP_PATH", message));
+    }
+
+    if let Some(munki_repo) = &prefs.munki_repo {
+        if !munki_repo.is_dir() {
+            problems.push(Problem::new(
+                Severity::Warning,
+                "MUNKI_REPO",
+                format!("{} doesn't exist or isn't a folder", munki_repo.display()),
+            ));
+        }
+    }
+
+    problems.extend(check_token_file(&prefs.github_token_path));
+    problems
+}
+
+/// Check that a folder exists, or that it could be created, returning what's
+/// wrong if not
+fn check_creatable_dir(dir: &Path) -> Option<String> {
+    if dir.is_dir() {
+        return None;
+    }
+    if dir.exists() {
+        return Some(format!("{} exists, but isn't a folder", dir.display()));
+    }
+    // It can be created if the closest folder that does exist is writable
+    let existing = dir.ancestors().skip(1).find(|parent| parent.exists())?;
+    match fs::metadata(existing) {
+        Ok(metadata) if !metadata.is_dir() => Some(format!(
+            "{} can't be created, because {} isn't a folder",
+            dir.display(),
+            existing.display()
+        )),
+        // Outside unix, the read-only attribute still gives a clearer
+        // message than the probe
+        #[cfg(not(unix))]
+        Ok(metadata) if metadata.permissions().readonly() => Some(format!(
+            "{} can't be created, because {} is read-only",
+            dir.display(),
+            existing.display()
+        )),
+        Ok(_) => match probe_writable_dir(existing) {
+            Ok(()) => None,
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Some(format!(
+                "{} can't be created, because {} isn't writable",
+                dir.display(),
+                existing.display()
+            )),
+            Err(e) => Some(format!("{} can't be created: {e}", dir.display())),
+        },
+        Err(e) => Some(format!("{} can't be created: {e}", dir.display())),
+    }
+}
+
+/// Check that something can be created in a folder by creating and removing
+/// an empty folder in it.
+///
+/// Permission bits don't account for ACLs, read-only mounts or who the
+/// folder belongs to, so actually trying is the only reliable check.
+fn probe_writable_dir(dir: &Path) -> io::Result<()> {
+    for attempt in 0.. {
+        let probe = dir.join(format!(".autopkg-check-{}-{attempt}", process::id()));
+        match fs::create_dir(&probe) {
+            Ok(()) => return fs::remove_dir(&probe),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!()
+}
+
+/// Check that the GitHub token file, if there is one, can be read and isn't
+/// readable by anyone else
+fn check_token_file(path: &Path) -> Option<Problem> {
+    const KEY: &str = "GITHUB_TOKEN_PATH";
+    if !path.exists() {
+        return None;
+    }
+    if let Err(e) = fs::File::open(path) {
+        return Some(Problem::new(
+            Severity::Error,
+            KEY,
+            format!("{} can't be read: {e}", path.display()),
+        ));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path).ok()?.permissions().mode();
+        if mode & 0o077 != 0 {
+            return Some(Problem::new(
+                Severity::Warning,
+                KEY,
+                format!(
+                    "{} can be read by other users (mode {:o}). Run chmod 600 on it",
+                    path.display(),
+                    mode & 0o777
+                ),
+            ));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_errors_have_positions() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = dir.path().join("prefs.json");
+        fs::write(&json, "{\n  \"CACHE_DIR\": \"/Cache\",\n  oops\n}").unwrap();
+        let problems = check_prefs_file(&json);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].position, Some((3, 3)));
+        assert!(problems[0]
+            .to_string()
+            .starts_with(&format!("error: {}:3:3: ", json.display())));
+
+        let yaml = dir.path().join("prefs.yaml");
+        fs::write(&yaml, "CACHE_DIR: /Cache\n  RECIPE_REPO_DIR: [\n").unwrap();
+        let problems = check_prefs_file(&yaml);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].position.is_some());
+
+        // Every bad value is found, not just the first
+        fs::write(
+            &json,
+            r#"{"RECIPE_SEARCH_DIRS": "/Recipes", "CACHE_DIR": 3, "CURL_PATH": "/curl"}"#,
+        )
+        .unwrap();
+        let problems = check_prefs_file(&json);
+        let keys: Vec<_> = problems
+            .iter()
+            .map(|p| (p.severity, p.key.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                (Severity::Error, "CACHE_DIR"),
+                (Severity::Warning, "CURL_PATH"),
+                (Severity::Error, "RECIPE_SEARCH_DIRS")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_preferences() {
+        let dir = tempfile::tempdir().unwrap();
+        let recipes = dir.path().join("Recipes");
+        fs::create_dir(&recipes).unwrap();
+        let not_a_folder = dir.path().join("file");
+        fs::write(&not_a_folder, "").unwrap();
+        let token = dir.path().join("gh_token");
+        fs::write(&token, "token").unwrap();
+
+        let mut prefs = Preferences::new();
+        prefs.recipe_search_dirs = vec![
+            recipes.to_owned(),
+            dir.path().join("Recipes/../Recipes"),
+            dir.path().join("Missing"),
+        ];
+        prefs.cache_dir = dir.path().join("Cache/will/be/created");
+        prefs.recipe_override_dir = not_a_folder.to_owned();
+        prefs.recipe_repo_dir = not_a_folder.join("RecipeRepos");
+        prefs.recipe_map_path = dir.path().join("recipe_map.json");
+        prefs.munki_repo = Some(dir.path().join("munki"));
+        prefs.github_token_path = token.to_owned();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&token, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+
+        let problems = check_preferences(&prefs);
+        let mut keys: Vec<_> = problems
+            .iter()
+            .map(|p| (p.severity, p.key.as_deref().unwrap()))
+            .collect();
+        let mut expected = vec![
+            (Severity::Warning, "RECIPE_SEARCH_DIRS"),
+            (Severity::Warning, "RECIPE_SEARCH_DIRS"),
+            (Severity::Error, "RECIPE_OVERRIDE_DIR"),
+            (Severity::Error, "RECIPE_REPO_DIR"),
+            (Severity::Warning, "MUNKI_REPO"),
+        ];
+        if cfg!(unix) {
+            expected.push((Severity::Warning, "GITHUB_TOKEN_PATH"));
+        }
+        keys.sort();
+        expected.sort();
+        assert_eq!(keys, expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_creatable_dir_in_a_read_only_folder() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let read_only = dir.path().join("read-only");
+        fs::create_dir(&read_only).unwrap();
+        fs::set_permissions(&read_only, fs::Permissions::from_mode(0o555)).unwrap();
+        let cache_dir = read_only.join("Cache");
+        let message = check_creatable_dir(&cache_dir);
+        // Root can create folders anywhere, so only the probe itself is
+        // checked when the tests run as root
+        let writable = fs::create_dir(read_only.join("probe")).is_ok();
+        fs::set_permissions(&read_only, fs::Permissions::from_mode(0o755)).unwrap();
+        if writable {
+            assert_eq!(message, None);
+        } else {
+            assert!(message.unwrap().ends_with("isn't writable"));
+        }
+        // The probe never leaves anything behind
+        assert!(fs::read_dir(&read_only)
+            .unwrap()
+            .all(|entry| entry.unwrap().file_name() == "probe"));
+        assert_eq!(check_creatable_dir(&cache_dir), None);
+    }
+
+    #[test]
+    fn test_diagnose_stops_at_unreadable_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let user = dir.path().join("user.json");
+        fs::write(&user, "{").unwrap();
+        let paths = PrefsPaths {
+            user: Some(user.to_owned()),
+            ..Default::default()
+        };
+        let problems = diagnose(&paths, Vec::new());
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].path.as_ref(), Some(&user));
+
+        fs::write(&user, r#"{"MUNKI_REPO": "/does/not/exist"}"#).unwrap();
+        let problems = diagnose(&paths, Vec::new());
+        assert!(problems
+            .iter()
+            .any(|p| p.key.as_deref() == Some("MUNKI_REPO")));
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::warn;
+
+use crate::error::Result;
+use crate::processors::{get_str, Environment, InputVariable, OutputVariable, Processor};
+use crate::recipes::PlistDataType;
+
+const DEFAULT_WARNING_MESSAGE: &str =
+    "### This recipe has been deprecated. It may be removed soon. ###";
+
+/// This processor outputs a warning that the recipe has been deprecated.
+pub struct DeprecationWarning;
+
+impl Processor for DeprecationWarning {
+    fn name(&self) -> &str {
+        "DeprecationWarning"
+    }
+
+    fn description(&self) -> &str {
+        "This processor outputs a warning that the recipe has been deprecated."
+    }
+
+    fn input_variables(&self) -> &[InputVariable] {
+        &[InputVariable {
+            name: "warning_message",
+            description: "Warning message to output.",
+            required: false,
+            default: None,
+        }]
+    }
+
+    fn output_variables(&self) -> &[OutputVariable] {
+        &[OutputVariable {
+            name: "deprecation_summary_result",
+            description: "Description of interesting results.",
+        }]
+    }
+
+    fn execute(&self, env: &mut Environment) -> Result<()> {
+        let warning_message = get_str(env, "warning_message")
+            .unwrap_or(DEFAULT_WARNING_MESSAGE)
+            .to_string();
+        warn!("{warning_message}");
+        let recipe_name = get_str(env, "RECIPE_PATH")
+            .and_then(|path| Path::new(path).file_stem())
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default()
+            .to_string();
+        env.insert(
+            "deprecation_summary_result".to_string(),
+            PlistDataType::Dict(BTreeMap::from([
+                (
+                    "summary_text".to_string(),
+                    PlistDataType::from("The following recipes have deprecation warnings:"),
+                ),
+                (
+                    "report_fields".to_string(),
+                    PlistDataType::from(vec!["name".to_string(), "warning".to_string()]),
+                ),
+                (
+                    "data".to_string(),
+                    PlistDataType::Dict(BTreeMap::from([
+                        ("name".to_string(), PlistDataType::from(recipe_name)),
+                        ("warning".to_string(), PlistDataType::from(warning_message)),
+                    ])),
+                ),
+            ])),
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+use crate::error::Result;
+use crate::processors::{Environment, Processor};
+
+/// This processor performs no action, but serves as a marker to signal
+/// where AutoPkg should stop when the -c/--check options are used.
+pub struct EndOfCheckPhase;
+
+impl Processor for EndOfCheckPhase {
+    fn name(&self) -> &str {
+        "EndOfCheckPhase"
+    }
+
+    fn description(&self) -> &str {
+        "This processor performs no action, but serves as a marker to signal \
+        where AutoPkg should stop when the -c/--check options are used."
+    }
+
+    fn execute(&self, _env: &mut Environment) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use std::fs;
+use tracing::info;
+
+use crate::error::{Error, Result};
+use crate::processors::{get_str, Environment, InputVariable, Processor};
+
+/// Create a file.
+pub struct FileCreator;
+
+impl FileCreator {
+    fn error(&self, message: String) -> Error {
+        Error::Processor {
+            processor: self.name().to_string(),
+            message,
+        }
+    }
+}
+
+impl Processor for FileCreator {
+    fn name(&self) -> &str {
+        "FileCreator"
+    }
+
+    fn description(&self) -> &str {
+        "Create a file."
+    }
+
+    fn input_variables(&self) -> &[InputVariable] {
+        &[
+            InputVariable {
+                name: "file_path",
+                description: "Path to a file to create.",
+                required: true,
+                default: None,
+            },
+            InputVariable {
+                name: "file_content",
+                description: "Contents to put in file.",
+                required: true,
+                default: None,
+            },
+            InputVariable {
+                name: "file_mode",
+                description: "String. Numeric mode for file in octal format.",
+                required: false,
+                default: None,
+            },
+        ]
+    }
+
+    fn execute(&self, env: &mut Environment) -> Result<()> {
+        let (Some(file_path), Some(file_content)) =
+            (get_str(env, "file_path"), get_str(env, "file_content"))
+        else {
+            return Err(self.error("file_path and file_content must be strings".to_string()));
+        };
+        fs::write(file_path, file_content)
+            .map_err(|e| self.error(format!("Can't create file at {file_path}: {e}")))?;
+        info!("Created file at {file_path}");
+        if let Some(file_mode) = get_str(env, "file_mode") {
+            set_file_mode(file_path, file_mode).map_err(|e| {
+                self.error(format!("Can't set mode of {file_path} to {file_mode}: {e}"))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Set the permissions of a file from an octal string like "0644"
+#[cfg(unix)]
+fn set_file_mode(file_path: &str, file_mode: &str) -> std::result::Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = u32::from_str_radix(file_mode, 8).map_err(|e| e.to_string())?;
+    fs::set_permissions(file_path, fs::Permissions::from_mode(mode)).map_err(|e| e.to_string())
+}
+
+/// File modes don't mean anything outside of unix, so they're ignored
+#[cfg(not(unix))]
+fn set_file_mode(_file_path: &str, _file_mode: &str) -> std::result::Result<(), String> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipes::PlistDataType;
+
+    #[test]
+    fn test_creates_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("created.txt");
+        let mut env = Environment::from([
+            (
+                "file_path".to_string(),
+                PlistDataType::from(file_path.to_string_lossy().to_string()),
+            ),
+            ("file_content".to_string(), PlistDataType::from("Hello")),
+            ("file_mode".to_string(), PlistDataType::from("0600")),
+        ]);
+        FileCreator.execute(&mut env).unwrap();
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "Hello");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&file_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // The folder it goes in has to exist already
+        env.insert(
+            "file_path".to_string(),
+            PlistDataType::from(
+                dir.path()
+                    .join("missing/file.txt")
+                    .to_string_lossy()
+                    .to_string(),
+            ),
+        );
+        assert!(matches!(
+            FileCreator.execute(&mut env),
+            Err(Error::Processor { .. })
+        ));
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::{BTreeMap, HashMap};
+use tracing::{debug, trace};
+
+use crate::error::{Error, Result};
+use crate::recipes::PlistDataType;
+
+mod deprecation_warning;
+mod end_of_check_phase;
+mod file_creator;
+mod path_deleter;
+
+pub use deprecation_warning::DeprecationWarning;
+pub use end_of_check_phase::EndOfCheckPhase;
+pub use file_creator::FileCreator;
+pub use path_deleter::PathDeleter;
+
+/// The environment is every variable available to a recipe while it runs,
+/// and is shared by every processor in the recipe
+pub type Environment = HashMap<String, PlistDataType>;
+
+/// An input variable a processor reads from the environment
+#[derive(Debug)]
+pub struct InputVariable {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Processing fails if a required variable isn't in the environment
+    pub required: bool,
+    /// Value set in the environment if the variable isn't already there
+    pub default: Option<&'static str>,
+}
+
+/// An output variable a processor writes to the environment
+#[derive(Debug)]
+pub struct OutputVariable {
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+/// Processors are the individual steps of a recipe
+pub trait Processor {
+    /// Name of the processor, as used in a recipe's Process array
+    fn name(&self) -> &str;
+
+    /// Human-readable description of what the processor does
+    fn description(&self) -> &str;
+
+    /// Variables this processor reads from the environment
+    fn input_variables(&self) -> &[InputVariable] {
+        &[]
+    }
+
+    /// Variables this processor writes to the environment
+    fn output_variables(&self) -> &[OutputVariable] {
+        &[]
+    }
+
+    /// Do the actual work of the processor.
+    ///
+    /// This should only be called through `process()`, which makes sure the
+    /// input variables are valid first.
+    fn execute(&self, env: &mut Environment) -> Result<()>;
+
+    /// Check the input variables against the environment, fill in any
+    /// defaults, and then execute the processor.
+    fn process(&self, env: &mut Environment) -> Result<()> {
+        for variable in self.input_variables() {
+            if env.contains_key(variable.name) {
+                continue;
+            }
+            if let Some(default) = variable.default {
+                trace!("Setting default {} = {default}", variable.name);
+                env.insert(
+                    variable.name.to_string(),
+                    PlistDataType::Str(default.to_string()),
+                );
+            } else if variable.required {
+                return Err(Error::Processor {
+                    processor: self.name().to_string(),
+                    message: format!("requires {}", variable.name),
+                });
+            }
+        }
+        debug!("Executing {}", self.name());
+        self.execute(env)
+    }
+}
+
+/// Split a processor name that may include a recipe identifier, in the form
+/// `com.example.identifier/ProcessorName`, into the processor name and the
+/// identifier.
+///
+/// The identifier is None if the name doesn't contain one.
+pub fn extract_processor_name_with_recipe_identifier(processor_name: &str) -> (&str, Option<&str>) {
+    match processor_name.split_once('/') {
+        Some((identifier, name)) => (name, Some(identifier)),
+        None => (processor_name, None),
+    }
+}
+
+/// A processor in the registry, along with whether it is a core processor
+struct RegisteredProcessor {
+    processor: Box<dyn Processor>,
+    core: bool,
+}
+
+/// All processors that recipes can use, keyed by name.
+///
+/// Core processors are the ones built in to AutoPkg. Any other processor is
+/// custom, and can be registered under a plain name, or shared by a recipe
+/// under `com.example.identifier/ProcessorName`.
+#[derive(Default)]
+pub struct ProcessorRegistry {
+    // We're using a BTreeMap so that processors are always listed in order
+    processors: BTreeMap<String, RegisteredProcessor>,
+}
+
+impl ProcessorRegistry {
+    /// Create a registry containing only the core processors
+    pub fn new() -> ProcessorRegistry {
+        let mut registry = ProcessorRegistry::default();
+        registry.register_core(Box::new(DeprecationWarning));
+        registry.register_core(Box::new(EndOfCheckPhase));
+        registry.register_core(Box::new(FileCreator));
+        registry.register_core(Box::new(PathDeleter));
+        registry
+    }
+
+    fn register_core(&mut self, processor: Box<dyn Processor>) {
+        self.processors.insert(
+            processor.name().to_string(),
+            RegisteredProcessor {
+                processor,
+                core: true,
+            },
+        );
+    }
+
+    /// Add a custom processor, optionally shared by the recipe with the given
+    /// identifier.
+    ///
+    /// A custom processor can never replace a core processor of the same
+    /// name; trying to do so returns false.
+    pub fn register_custom(
+        &mut self,
+        recipe_identifier: Option<&str>,
+        processor: Box<dyn Processor>,
+    ) -> bool {
+        let name = match recipe_identifier {
+            Some(identifier) => format!("{identifier}/{}", processor.name()),
+            None => processor.name().to_string(),
+        };
+        if self.is_core(&name) {
+            return false;
+        }
+        debug!("Registering custom processor {name}");
+        self.processors.insert(
+            name,
+            RegisteredProcessor {
+                processor,
+                core: false,
+            },
+        );
+        true
+    }
+
+    /// Find the registry entry for a name.
+    ///
+    /// A shared processor name that isn't registered falls back to the plain
+    /// processor name, the same as Python AutoPkg.
+    fn lookup(&self, name: &str) -> Option<&RegisteredProcessor> {
+        self.processors.get(name).or_else(|| {
+            let (processor_name, _) = extract_processor_name_with_recipe_identifier(name);
+            self.processors.get(processor_name)
+        })
+    }
+
+    /// Get a processor by name
+    pub fn get(&self, name: &str) -> Result<&dyn Processor> {
+        self.lookup(name)
+            .map(|registered| registered.processor.as_ref())
+            .ok_or_else(|| Error::ProcessorNotFound(name.to_string()))
+    }
+
+    /// Whether a processor name refers to a core processor
+    pub fn is_core(&self, name: &str) -> bool {
+        self.lookup(name)
+            .map(|registered| registered.core)
+            .unwrap_or(false)
+    }
+
+    /// Names of all core processors, sorted
+    pub fn core_processor_names(&self) -> Vec<&str> {
+        self.processor_names(true)
+    }
+
+    /// Names of all custom processors, sorted
+    pub fn custom_processor_names(&self) -> Vec<&str> {
+        self.processor_names(false)
+    }
+
+    fn processor_names(&self, core: bool) -> Vec<&str> {
+        self.processors
+            .iter()
+            .filter(|(_, registered)| registered.core == core)
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+}
+
+/// Get a string value from the environment.
+///
+/// Returns None if the key is missing or isn't a string.
+pub fn get_str<'a>(env: &'a Environment, key: &str) -> Option<&'a str> {
+    match env.get(key) {
+        Some(PlistDataType::Str(value)) => Some(value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A custom processor that does nothing, for registry tests
+    struct TestProcessor;
+
+    impl Processor for TestProcessor {
+        fn name(&self) -> &str {
+            "TestProcessor"
+        }
+
+        fn description(&self) -> &str {
+            "Does nothing."
+        }
+
+        fn input_variables(&self) -> &[InputVariable] {
+            &[
+                InputVariable {
+                    name: "required_input",
+                    description: "Must be set.",
+                    required: true,
+                    default: None,
+                },
+                InputVariable {
+                    name: "optional_input",
+                    description: "Has a default.",
+                    required: false,
+                    default: Some("default value"),
+                },
+            ]
+        }
+
+        fn execute(&self, _env: &mut Environment) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_extract_processor_name_with_recipe_identifier() {
+        assert_eq!(
+            extract_processor_name_with_recipe_identifier("com.example.shared/TestProcessor"),
+            ("TestProcessor", Some("com.example.shared"))
+        );
+        assert_eq!(
+            extract_processor_name_with_recipe_identifier("TestProcessor"),
+            ("TestProcessor", None)
+        );
+    }
+
+    #[test]
+    fn test_registry_core_and_custom() {
+        let mut registry = ProcessorRegistry::new();
+        assert!(registry.is_core("EndOfCheckPhase"));
+        assert!(registry.custom_processor_names().is_empty());
+
+        assert!(registry.register_custom(Some("com.example.shared"), Box::new(TestProcessor)));
+        assert_eq!(
+            registry.custom_processor_names(),
+            vec!["com.example.shared/TestProcessor"]
+        );
+        assert!(!registry.is_core("com.example.shared/TestProcessor"));
+        assert!(registry.get("com.example.shared/TestProcessor").is_ok());
+        // The plain name wasn't registered
+        assert!(matches!(
+            registry.get("TestProcessor"),
+            Err(Error::ProcessorNotFound(_))
+        ));
+        // A shared name falls back to a core processor of the same name
+        assert!(registry.is_core("com.example.shared/EndOfCheckPhase"));
+    }
+
+    #[test]
+    fn test_register_custom_cannot_replace_core() {
+        struct FakeEndOfCheckPhase;
+        impl Processor for FakeEndOfCheckPhase {
+            fn name(&self) -> &str {
+                "EndOfCheckPhase"
+            }
+            fn description(&self) -> &str {
+                "Not the real one."
+            }
+            fn execute(&self, _env: &mut Environment) -> Result<()> {
+                Ok(())
+            }
+        }
+        let mut registry = ProcessorRegistry::new();
+        assert!(!registry.register_custom(None, Box::new(FakeEndOfCheckPhase)));
+        assert!(registry.is_core("EndOfCheckPhase"));
+    }
+
+    #[test]
+    fn test_process_checks_input_variables() {
+        let mut env = Environment::new();
+        let err = TestProcessor.process(&mut env).unwrap_err();
+        assert!(matches!(err, Error::Processor { .. }));
+
+        env.insert(
+            "required_input".to_string(),
+            PlistDataType::Str("set".to_string()),
+        );
+        TestProcessor.process(&mut env).unwrap();
+        assert_eq!(get_str(&env, "optional_input"), Some("default value"));
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+use crate::error::{Error, Result};
+use crate::processors::{Environment, InputVariable, Processor};
+use crate::recipes::PlistDataType;
+
+/// Deletes file paths.
+pub struct PathDeleter;
+
+impl PathDeleter {
+    fn error(&self, message: String) -> Error {
+        Error::Processor {
+            processor: self.name().to_string(),
+            message,
+        }
+    }
+}
+
+impl Processor for PathDeleter {
+    fn name(&self) -> &str {
+        "PathDeleter"
+    }
+
+    fn description(&self) -> &str {
+        "Deletes file paths."
+    }
+
+    fn input_variables(&self) -> &[InputVariable] {
+        &[InputVariable {
+            name: "path_list",
+            description: "An array or list of pathnames to be deleted, \
+                even if that list contains a single item.",
+            required: true,
+            default: None,
+        }]
+    }
+
+    fn execute(&self, env: &mut Environment) -> Result<()> {
+        // If the recipe writer gave us a single string instead of a list of
+        // strings, treat it as a list of one
+        let path_list = match env.get("path_list") {
+            None => return Err(self.error("requires path_list".to_string())),
+            Some(PlistDataType::Str(path)) => vec![path.to_owned()],
+            Some(PlistDataType::Array(paths)) => paths
+                .iter()
+                .map(|path| path.as_str().map(str::to_string))
+                .collect::<Option<Vec<String>>>()
+                .ok_or_else(|| self.error("path_list must be a list of strings".to_string()))?,
+            _ => return Err(self.error("path_list must be a list of strings".to_string())),
+        };
+        for path in path_list {
+            let path = Path::new(&path);
+            let result = if path.is_file() || path.is_symlink() {
+                fs::remove_file(path)
+            } else if path.is_dir() {
+                fs::remove_dir_all(path)
+            } else {
+                return Err(self.error(format!(
+                    "Could not remove {} - it does not exist!",
+                    path.display()
+                )));
+            };
+            result.map_err(|e| self.error(format!("Could not remove {}: {e}", path.display())))?;
+            info!("Deleted {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deletes_files_and_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        let folder = dir.path().join("folder");
+        fs::write(&file, "delete me").unwrap();
+        fs::create_dir_all(folder.join("nested")).unwrap();
+        fs::write(folder.join("nested/file.txt"), "me too").unwrap();
+
+        let mut env = Environment::from([(
+            "path_list".to_string(),
+            PlistDataType::from(vec![
+                file.to_string_lossy().to_string(),
+                folder.to_string_lossy().to_string(),
+            ]),
+        )]);
+        PathDeleter.execute(&mut env).unwrap();
+        assert!(!file.exists());
+        assert!(!folder.exists());
+
+        // A single string is a list of one, and a missing path is an error
+        env.insert(
+            "path_list".to_string(),
+            PlistDataType::from(file.to_string_lossy().to_string()),
+        );
+        assert!(matches!(
+            PathDeleter.execute(&mut env),
+            Err(Error::Processor { .. })
+        ));
+    }
+
+    #[test]
+    fn test_missing_path_list_is_an_error() {
+        let mut env = Environment::new();
+        assert!(matches!(
+            PathDeleter.execute(&mut env),
+            Err(Error::Processor { .. })
+        ));
+    }
+}
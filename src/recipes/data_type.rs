@@ -0,0 +1,549 @@
+use base64::prelude::{Engine, BASE64_STANDARD};
+use serde::de::{self, Deserialize, Deserializer, EnumAccess, MapAccess, SeqAccess, VariantAccess};
+use serde::ser::{Serialize, Serializer};
+use serde_yaml::value::{Tag, TaggedValue};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::error::ParseError;
+
+// The plist deserializer only hands dates and UIDs to visitors when asked
+// for a plist value by name, and then as enum variants with these names.
+// These are otherwise private to the plist crate.
+const PLIST_VALUE: &str = "PLIST-VALUE";
+const PLIST_DATE: &str = "PLIST-DATE";
+const PLIST_UID: &str = "PLIST-UID";
+/// YAML tag used for binary data. serde_yaml resolves the standard !!binary
+/// tag to a plain string, so data is written with a local tag instead
+const YAML_BINARY_TAG: &str = "binary";
+
+/// Any value that can be stored in a plist or yaml recipe
+///
+/// Dictionaries are sorted by key, which is also the order they're written
+/// back out in, the same as Python AutoPkg.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlistDataType {
+    Array(Vec<PlistDataType>),
+    Bool(bool),
+    Data(Vec<u8>),
+    Date(plist::Date),
+    Dict(BTreeMap<String, PlistDataType>),
+    Integer(plist::Integer),
+    /// Only YAML can express an empty value, such as `KEY:` with nothing
+    /// after it. These can't be written to a plist.
+    Null,
+    Real(f64),
+    Str(String),
+}
+
+impl PlistDataType {
+    /// Get the value as a string slice, if it is a string
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            PlistDataType::Str(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get the value as an array, if it is an array
+    pub fn as_array(&self) -> Option<&[PlistDataType]> {
+        match self {
+            PlistDataType::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    /// Get the value as a dictionary, if it is a dictionary
+    pub fn as_dict(&self) -> Option<&BTreeMap<String, PlistDataType>> {
+        match self {
+            PlistDataType::Dict(dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    /// Format a scalar value the way Python's `str()` would, so it can be
+    /// substituted into a string. Arrays, dictionaries and data have no
+    /// sensible text form, so they return None.
+    pub fn to_python_string(&self) -> Option<String> {
+        match self {
+            PlistDataType::Str(value) => Some(value.to_owned()),
+            PlistDataType::Bool(true) => Some("True".to_string()),
+            PlistDataType::Bool(false) => Some("False".to_string()),
+            PlistDataType::Integer(value) => Some(value.to_string()),
+            PlistDataType::Real(value) => Some(python_float_string(*value)),
+            // plistlib reads dates as naive datetimes in UTC
+            PlistDataType::Date(date) => {
+                Some(date.to_xml_format().replace('T', " ").replace('Z', ""))
+            }
+            PlistDataType::Null => Some("None".to_string()),
+            PlistDataType::Array(_) | PlistDataType::Dict(_) | PlistDataType::Data(_) => None,
+        }
+    }
+}
+
+/// Format a float like Python's `repr()`: the shortest digits that round
+/// trip, always with a decimal point, and in scientific notation when the
+/// exponent is below -4 or at least 16
+fn python_float_string(value: f64) -> String {
+    if value.is_nan() {
+        return "nan".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    // Rust's exponent format already uses the shortest round-trip digits
+    let scientific = format!("{value:e}");
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa),
+    };
+    let digits = mantissa.replace('.', "");
+    if !(-4..16).contains(&exponent) {
+        let mantissa = match digits.split_at(1) {
+            (first, "") => first.to_string(),
+            (first, rest) => format!("{first}.{rest}"),
+        };
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        return format!("{sign}{mantissa}e{exponent_sign}{:02}", exponent.abs());
+    }
+    if exponent < 0 {
+        let zeros = "0".repeat((-exponent - 1) as usize);
+        return format!("{sign}0.{zeros}{digits}");
+    }
+    let point = exponent as usize + 1;
+    if digits.len() > point {
+        format!("{sign}{}.{}", &digits[..point], &digits[point..])
+    } else {
+        format!("{sign}{digits}{}.0", "0".repeat(point - digits.len()))
+    }
+}
+
+impl From<&str> for PlistDataType {
+    fn from(value: &str) -> Self {
+        PlistDataType::Str(value.to_string())
+    }
+}
+
+impl From<String> for PlistDataType {
+    fn from(value: String) -> Self {
+        PlistDataType::Str(value)
+    }
+}
+
+impl From<bool> for PlistDataType {
+    fn from(value: bool) -> Self {
+        PlistDataType::Bool(value)
+    }
+}
+
+impl From<Vec<String>> for PlistDataType {
+    fn from(values: Vec<String>) -> Self {
+        PlistDataType::Array(values.into_iter().map(PlistDataType::Str).collect())
+    }
+}
+
+impl Serialize for PlistDataType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            PlistDataType::Array(array) => array.serialize(serializer),
+            PlistDataType::Bool(value) => serializer.serialize_bool(*value),
+            PlistDataType::Data(data) => serializer.serialize_bytes(data),
+            PlistDataType::Date(date) => date.serialize(serializer),
+            PlistDataType::Dict(dict) => dict.serialize(serializer),
+            PlistDataType::Integer(value) => value.serialize(serializer),
+            PlistDataType::Null => serializer.serialize_unit(),
+            PlistDataType::Real(value) => serializer.serialize_f64(*value),
+            PlistDataType::Str(value) => serializer.serialize_str(value),
+        }
+    }
+}
+
+/// Visits any value from the plist or yaml deserializers
+struct PlistDataTypeVisitor;
+
+impl<'de> de::Visitor<'de> for PlistDataTypeVisitor {
+    type Value = PlistDataType;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any plist or yaml value")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<PlistDataType, E> {
+        Ok(PlistDataType::Bool(value))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<PlistDataType, E> {
+        Ok(PlistDataType::Integer(value.into()))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<PlistDataType, E> {
+        Ok(PlistDataType::Integer(value.into()))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<PlistDataType, E> {
+        Ok(PlistDataType::Real(value))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<PlistDataType, E> {
+        Ok(PlistDataType::Str(value.to_string()))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<PlistDataType, E> {
+        Ok(PlistDataType::Str(value))
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<PlistDataType, E> {
+        Ok(PlistDataType::Data(value.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, value: Vec<u8>) -> Result<PlistDataType, E> {
+        Ok(PlistDataType::Data(value))
+    }
+
+    fn visit_unit<E>(self) -> Result<PlistDataType, E> {
+        Ok(PlistDataType::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<PlistDataType, E> {
+        Ok(PlistDataType::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<PlistDataType, D::Error> {
+        PlistDataType::deserialize(deserializer)
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<PlistDataType, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<PlistDataType, A::Error> {
+        let mut array = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(value) = seq.next_element()? {
+            array.push(value);
+        }
+        Ok(PlistDataType::Array(array))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<PlistDataType, A::Error> {
+        let mut dict = BTreeMap::new();
+        while let Some((key, value)) = map.next_entry()? {
+            dict.insert(key, value);
+        }
+        Ok(PlistDataType::Dict(dict))
+    }
+
+    /// Plist dates and UIDs, and tagged yaml values, all arrive as enums
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<PlistDataType, A::Error> {
+        let (name, variant) = data.variant::<String>()?;
+        match name.as_str() {
+            PLIST_DATE => Ok(PlistDataType::Date(variant.newtype_variant()?)),
+            PLIST_UID => {
+                let uid: plist::Uid = variant.newtype_variant()?;
+                Ok(PlistDataType::Integer(uid.get().into()))
+            }
+            YAML_BINARY_TAG => {
+                let encoded: String = variant.newtype_variant()?;
+                BASE64_STANDARD
+                    .decode(encoded.trim())
+                    .map(PlistDataType::Data)
+                    .map_err(de::Error::custom)
+            }
+            // Any other yaml tag has no meaning to AutoPkg, so keep the value
+            _ => variant.newtype_variant(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PlistDataType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Other deserializers treat this as a transparent wrapper and go
+        // straight to visit_newtype_struct
+        deserializer.deserialize_newtype_struct(PLIST_VALUE, PlistDataTypeVisitor)
+    }
+}
+
+impl From<plist::Value> for PlistDataType {
+    fn from(value: plist::Value) -> Self {
+        match value {
+            plist::Value::Array(array) => {
+                PlistDataType::Array(array.into_iter().map(PlistDataType::from).collect())
+            }
+            plist::Value::Dictionary(dict) => PlistDataType::Dict(
+                dict.into_iter()
+                    .map(|(key, value)| (key, PlistDataType::from(value)))
+                    .collect(),
+            ),
+            plist::Value::Boolean(value) => PlistDataType::Bool(value),
+            plist::Value::Data(data) => PlistDataType::Data(data),
+            plist::Value::Date(date) => PlistDataType::Date(date),
+            plist::Value::Real(value) => PlistDataType::Real(value),
+            plist::Value::Integer(value) => PlistDataType::Integer(value),
+            plist::Value::String(value) => PlistDataType::Str(value),
+            plist::Value::Uid(uid) => PlistDataType::Integer(uid.get().into()),
+            // plist::Value is non-exhaustive, but every kind of value a plist
+            // file can hold is covered above
+            _ => PlistDataType::Null,
+        }
+    }
+}
+
+impl TryFrom<PlistDataType> for plist::Value {
+    type Error = ParseError;
+
+    /// Convert to a plist value, which fails only if there's a Null anywhere
+    /// in the value
+    fn try_from(value: PlistDataType) -> Result<Self, Self::Error> {
+        Ok(match value {
+            PlistDataType::Array(array) => plist::Value::Array(
+                array
+                    .into_iter()
+                    .map(plist::Value::try_from)
+                    .collect::<Result<_, _>>()?,
+            ),
+            PlistDataType::Bool(value) => plist::Value::Boolean(value),
+            PlistDataType::Data(data) => plist::Value::Data(data),
+            PlistDataType::Date(date) => plist::Value::Date(date),
+            PlistDataType::Dict(dict) => plist::Value::Dictionary(
+                dict.into_iter()
+                    .map(|(key, value)| Ok((key, plist::Value::try_from(value)?)))
+                    .collect::<Result<_, ParseError>>()?,
+            ),
+            PlistDataType::Integer(value) => plist::Value::Integer(value),
+            PlistDataType::Null => return Err(ParseError::NullInPlist),
+            PlistDataType::Real(value) => plist::Value::Real(value),
+            PlistDataType::Str(value) => plist::Value::String(value),
+        })
+    }
+}
+
+/// YAML allows keys of any type, but recipes only use strings, so any other
+/// scalar key is converted to its string form
+fn yaml_key_to_string(key: serde_yaml::Value) -> String {
+    match key {
+        serde_yaml::Value::String(key) => key,
+        serde_yaml::Value::Bool(key) => key.to_string(),
+        serde_yaml::Value::Number(key) => key.to_string(),
+        serde_yaml::Value::Null => "null".to_string(),
+        key => serde_yaml::to_string(&key)
+            .map(|key| key.trim_end().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+impl From<serde_yaml::Value> for PlistDataType {
+    fn from(value: serde_yaml::Value) -> Self {
+        match value {
+            serde_yaml::Value::Null => PlistDataType::Null,
+            serde_yaml::Value::Bool(value) => PlistDataType::Bool(value),
+            serde_yaml::Value::Number(number) => {
+                if let Some(value) = number.as_i64() {
+                    PlistDataType::Integer(value.into())
+                } else if let Some(value) = number.as_u64() {
+                    PlistDataType::Integer(value.into())
+                } else {
+                    PlistDataType::Real(number.as_f64().unwrap_or(f64::NAN))
+                }
+            }
+            serde_yaml::Value::String(value) => PlistDataType::Str(value),
+            serde_yaml::Value::Sequence(array) => {
+                PlistDataType::Array(array.into_iter().map(PlistDataType::from).collect())
+            }
+            serde_yaml::Value::Mapping(dict) => PlistDataType::Dict(
+                dict.into_iter()
+                    .map(|(key, value)| (yaml_key_to_string(key), PlistDataType::from(value)))
+                    .collect(),
+            ),
+            serde_yaml::Value::Tagged(tagged) => {
+                let TaggedValue { tag, value } = *tagged;
+                match (&value, tag == YAML_BINARY_TAG) {
+                    (serde_yaml::Value::String(encoded), true) => {
+                        match BASE64_STANDARD.decode(encoded.trim()) {
+                            Ok(data) => PlistDataType::Data(data),
+                            Err(_) => PlistDataType::from(value),
+                        }
+                    }
+                    _ => PlistDataType::from(value),
+                }
+            }
+        }
+    }
+}
+
+impl From<PlistDataType> for serde_yaml::Value {
+    /// Convert to a yaml value. YAML has no date type, so dates become
+    /// strings in the same format plists use.
+    fn from(value: PlistDataType) -> Self {
+        match value {
+            PlistDataType::Array(array) => serde_yaml::Value::Sequence(
+                array.into_iter().map(serde_yaml::Value::from).collect(),
+            ),
+            PlistDataType::Bool(value) => serde_yaml::Value::Bool(value),
+            PlistDataType::Data(data) => serde_yaml::Value::Tagged(Box::new(TaggedValue {
+                tag: Tag::new(YAML_BINARY_TAG),
+                value: serde_yaml::Value::String(BASE64_STANDARD.encode(data)),
+            })),
+            PlistDataType::Date(date) => serde_yaml::Value::String(date.to_xml_format()),
+            PlistDataType::Dict(dict) => serde_yaml::Value::Mapping(
+                dict.into_iter()
+                    .map(|(key, value)| (serde_yaml::Value::String(key), value.into()))
+                    .collect(),
+            ),
+            PlistDataType::Integer(value) => match value.as_signed() {
+                Some(value) => serde_yaml::Value::Number(value.into()),
+                None => serde_yaml::Value::Number(value.as_unsigned().unwrap_or_default().into()),
+            },
+            PlistDataType::Null => serde_yaml::Value::Null,
+            PlistDataType::Real(value) => serde_yaml::Value::Number(value.into()),
+            PlistDataType::Str(value) => serde_yaml::Value::String(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A plist using every kind of value
+    const TEST_PLIST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>MAX_RETRIES</key>
+	<integer>3</integer>
+	<key>BIG</key>
+	<integer>18446744073709551615</integer>
+	<key>NEGATIVE</key>
+	<integer>-1</integer>
+	<key>THRESHOLD</key>
+	<real>0.5</real>
+	<key>RELEASED</key>
+	<date>2024-01-02T03:04:05Z</date>
+	<key>BLOB</key>
+	<data>aGVsbG8=</data>
+	<key>MIXED</key>
+	<array>
+		<string>one</string>
+		<integer>2</integer>
+		<true/>
+		<dict>
+			<key>nested</key>
+			<dict>
+				<key>deeper</key>
+				<array>
+					<false/>
+				</array>
+			</dict>
+		</dict>
+	</array>
+</dict>
+</plist>"#;
+
+    #[test]
+    fn test_deserialize_every_plist_type() {
+        let value: PlistDataType = plist::from_bytes(TEST_PLIST.as_bytes()).unwrap();
+        let dict = value.as_dict().unwrap();
+        assert_eq!(dict["MAX_RETRIES"], PlistDataType::Integer(3.into()));
+        assert_eq!(dict["BIG"], PlistDataType::Integer(u64::MAX.into()));
+        assert_eq!(dict["NEGATIVE"], PlistDataType::Integer((-1).into()));
+        assert_eq!(dict["THRESHOLD"], PlistDataType::Real(0.5));
+        assert_eq!(
+            dict["RELEASED"],
+            PlistDataType::Date(plist::Date::from_xml_format("2024-01-02T03:04:05Z").unwrap())
+        );
+        assert_eq!(dict["BLOB"], PlistDataType::Data(b"hello".to_vec()));
+        let mixed = dict["MIXED"].as_array().unwrap();
+        assert_eq!(mixed[0], PlistDataType::from("one"));
+        assert_eq!(mixed[2], PlistDataType::Bool(true));
+        assert_eq!(
+            mixed[3].as_dict().unwrap()["nested"].as_dict().unwrap()["deeper"],
+            PlistDataType::Array(vec![PlistDataType::Bool(false)])
+        );
+        // Deserializing directly gives the same result as going through a
+        // plist::Value
+        let plist_value = plist::Value::from_reader_xml(TEST_PLIST.as_bytes()).unwrap();
+        assert_eq!(PlistDataType::from(plist_value.clone()), value);
+        assert_eq!(plist::Value::try_from(value).unwrap(), plist_value);
+    }
+
+    #[test]
+    fn test_plist_round_trip() {
+        let value: PlistDataType = plist::from_bytes(TEST_PLIST.as_bytes()).unwrap();
+        let mut written = Vec::new();
+        plist::to_writer_xml(&mut written, &value).unwrap();
+        let reread: PlistDataType = plist::from_bytes(&written).unwrap();
+        assert_eq!(reread, value);
+    }
+
+    #[test]
+    fn test_yaml_round_trip() {
+        let yaml = "\
+MAX_RETRIES: 3
+THRESHOLD: 0.5
+EMPTY:
+BLOB: !binary aGVsbG8=
+MIXED:
+- one
+- 2
+- true
+- nested:
+    deeper:
+    - false
+";
+        let value: PlistDataType = serde_yaml::from_str(yaml).unwrap();
+        let dict = value.as_dict().unwrap();
+        assert_eq!(dict["MAX_RETRIES"], PlistDataType::Integer(3.into()));
+        assert_eq!(dict["THRESHOLD"], PlistDataType::Real(0.5));
+        assert_eq!(dict["EMPTY"], PlistDataType::Null);
+        assert_eq!(dict["BLOB"], PlistDataType::Data(b"hello".to_vec()));
+
+        // Deserializing directly gives the same result as going through a
+        // serde_yaml::Value, and converting back is lossless
+        let yaml_value: serde_yaml::Value = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(PlistDataType::from(yaml_value), value);
+        let converted = serde_yaml::Value::from(value.clone());
+        assert_eq!(PlistDataType::from(converted), value);
+    }
+
+    #[test]
+    fn test_null_cannot_be_a_plist() {
+        let value = PlistDataType::Array(vec![PlistDataType::Null]);
+        assert!(matches!(
+            plist::Value::try_from(value),
+            Err(ParseError::NullInPlist)
+        ));
+    }
+
+    #[test]
+    fn test_to_python_string() {
+        let python_string = |value: PlistDataType| value.to_python_string().unwrap();
+        assert_eq!(python_string(PlistDataType::Bool(false)), "False");
+        assert_eq!(python_string(PlistDataType::Integer((-1).into())), "-1");
+        assert_eq!(python_string(PlistDataType::Null), "None");
+        assert_eq!(
+            python_string(PlistDataType::Date(
+                plist::Date::from_xml_format("2024-01-02T03:04:05Z").unwrap()
+            )),
+            "2024-01-02 03:04:05"
+        );
+        // Floats always have a decimal point, and switch to scientific
+        // notation at the same exponents as Python
+        for (value, expected) in [
+            (1.0, "1.0"),
+            (-0.5, "-0.5"),
+            (128.25, "128.25"),
+            (0.0001, "0.0001"),
+            (0.00001, "1e-05"),
+            (1e15, "1000000000000000.0"),
+            (1.5e16, "1.5e+16"),
+            (f64::INFINITY, "inf"),
+        ] {
+            assert_eq!(python_string(PlistDataType::Real(value)), expected);
+        }
+        assert_eq!(PlistDataType::Data(vec![1]).to_python_string(), None);
+    }
+}
@@ -0,0 +1,202 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{debug, warn};
+
+use crate::constants::AUTOPKGIGNORE_FILENAME;
+use crate::SearchDirSettings;
+
+/// Glob patterns that apply to everything inside one folder
+#[derive(Debug)]
+struct IgnorePatterns {
+    folder: PathBuf,
+    globs: GlobSet,
+}
+
+impl IgnorePatterns {
+    /// Build the patterns for a folder, skipping any that aren't valid globs.
+    ///
+    /// A pattern without a slash matches a file or folder name at any depth,
+    /// like in a .gitignore file. Anything else is matched against the path
+    /// relative to the folder.
+    fn new<'a>(folder: &Path, patterns: impl IntoIterator<Item = &'a str>) -> IgnorePatterns {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            // Trailing slashes only mark folders, and a leading slash anchors
+            // the pattern to the folder, which every pattern with a slash is
+            let trimmed = pattern.trim_end_matches('/');
+            let anchored = trimmed.contains('/');
+            let trimmed = trimmed.trim_start_matches('/');
+            let glob = if anchored {
+                trimmed.to_string()
+            } else {
+                format!("**/{trimmed}")
+            };
+            match GlobBuilder::new(&glob).literal_separator(true).build() {
+                Ok(glob) => {
+                    builder.add(glob);
+                }
+                Err(e) => warn!(
+                    "Ignoring invalid pattern {pattern} for {}: {e}",
+                    folder.display()
+                ),
+            }
+        }
+        let globs = builder.build().unwrap_or_else(|e| {
+            warn!(
+                "Unable to use ignore patterns for {}: {e}",
+                folder.display()
+            );
+            GlobSet::empty()
+        });
+        IgnorePatterns {
+            folder: folder.to_path_buf(),
+            globs,
+        }
+    }
+
+    /// Read the patterns in a folder's .autopkgignore file, one per line.
+    ///
+    /// Blank lines and lines starting with # are skipped. Returns None if
+    /// the folder doesn't have one.
+    fn from_ignore_file(folder: &Path) -> Option<IgnorePatterns> {
+        let ignore_path = folder.join(AUTOPKGIGNORE_FILENAME);
+        let contents = fs::read_to_string(&ignore_path).ok()?;
+        debug!("Using ignore patterns from {}", ignore_path.display());
+        let patterns = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        Some(IgnorePatterns::new(folder, patterns))
+    }
+
+    fn is_match(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.folder)
+            .map(|relative| self.globs.is_match(relative))
+            .unwrap_or(false)
+    }
+}
+
+/// Decides which files and folders to skip while searching one search dir
+/// for recipes
+#[derive(Debug)]
+pub(crate) struct IgnoreRules {
+    search_dir: PathBuf,
+    settings_patterns: IgnorePatterns,
+    use_autopkgignore: bool,
+    /// The .autopkgignore patterns of every folder seen so far, if it has any
+    ignore_files: HashMap<PathBuf, Option<IgnorePatterns>>,
+    /// Modification time of every .autopkgignore file that's been read
+    read_files: BTreeMap<PathBuf, Option<SystemTime>>,
+}
+
+impl IgnoreRules {
+    pub(crate) fn new(search_dir: &Path, settings: &SearchDirSettings) -> IgnoreRules {
+        IgnoreRules {
+            search_dir: search_dir.to_path_buf(),
+            settings_patterns: IgnorePatterns::new(
+                search_dir,
+                settings.ignore.iter().map(String::as_str),
+            ),
+            use_autopkgignore: settings.use_autopkgignore,
+            ignore_files: HashMap::new(),
+            read_files: BTreeMap::new(),
+        }
+    }
+
+    /// The modification time of every .autopkgignore file read so far, as it
+    /// was just before the file was read
+    pub(crate) fn read_files(&self) -> &BTreeMap<PathBuf, Option<SystemTime>> {
+        &self.read_files
+    }
+
+    /// Whether a path inside the search dir should be skipped.
+    ///
+    /// A .autopkgignore file applies to its own folder and every folder
+    /// below it.
+    pub(crate) fn is_ignored(&mut self, path: &Path) -> bool {
+        if self.settings_patterns.is_match(path) {
+            return true;
+        }
+        if !self.use_autopkgignore {
+            return false;
+        }
+        for folder in path
+            .ancestors()
+            .skip(1)
+            .take_while(|folder| folder.starts_with(&self.search_dir))
+        {
+            let read_files = &mut self.read_files;
+            let patterns = self
+                .ignore_files
+                .entry(folder.to_path_buf())
+                .or_insert_with(|| {
+                    // The time is taken first, so an edit made while the
+                    // file is read still counts as a change
+                    let ignore_path = folder.join(AUTOPKGIGNORE_FILENAME);
+                    let modified = fs::metadata(&ignore_path).and_then(|m| m.modified()).ok();
+                    let patterns = IgnorePatterns::from_ignore_file(folder);
+                    if patterns.is_some() {
+                        read_files.insert(ignore_path, modified);
+                    }
+                    patterns
+                });
+            if patterns.as_ref().is_some_and(|p| p.is_match(path)) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_patterns() {
+        let patterns = IgnorePatterns::new(
+            Path::new("/repo"),
+            ["Tests", "/Archive/*.recipe", "*.recipe.plist"],
+        );
+        // Patterns without a slash match at any depth
+        assert!(patterns.is_match(Path::new("/repo/Tests")));
+        assert!(patterns.is_match(Path::new("/repo/Firefox/Tests")));
+        assert!(patterns.is_match(Path::new("/repo/Firefox/Firefox.munki.recipe.plist")));
+        // Anything else is relative to the folder
+        assert!(patterns.is_match(Path::new("/repo/Archive/Old.download.recipe")));
+        assert!(!patterns.is_match(Path::new("/repo/Firefox/Archive/Old.download.recipe")));
+        assert!(!patterns.is_match(Path::new("/repo/Firefox/Firefox.download.recipe")));
+        // And nothing outside the folder ever matches
+        assert!(!patterns.is_match(Path::new("/other/Tests")));
+    }
+
+    #[test]
+    fn test_ignore_rules_use_nested_ignore_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repo");
+        fs::create_dir_all(repo.join("Firefox")).unwrap();
+        fs::write(
+            repo.join(AUTOPKGIGNORE_FILENAME),
+            "# Test fixtures\nFixtures/\n\n",
+        )
+        .unwrap();
+        fs::write(repo.join("Firefox").join(AUTOPKGIGNORE_FILENAME), "Old.*").unwrap();
+
+        let mut settings = SearchDirSettings::default();
+        let mut rules = IgnoreRules::new(dir.path(), &settings);
+        assert!(rules.is_ignored(&repo.join("Fixtures")));
+        assert!(rules.is_ignored(&repo.join("Firefox/Fixtures")));
+        assert!(rules.is_ignored(&repo.join("Firefox/Old.download.recipe")));
+        assert!(!rules.is_ignored(&repo.join("Old.download.recipe")));
+        assert!(!rules.is_ignored(&repo.join("Firefox/Firefox.download.recipe")));
+
+        settings.use_autopkgignore = false;
+        settings.ignore = vec!["Firefox".to_string()];
+        let mut rules = IgnoreRules::new(dir.path(), &settings);
+        assert!(!rules.is_ignored(&repo.join("Fixtures")));
+        assert!(rules.is_ignored(&repo.join("Firefox")));
+    }
+}
@@ -1,37 +1,70 @@
-use anyhow::Result;
-use plist::Value;
-use serde::{Deserialize, Serialize};
+use indexmap::IndexMap;
+use once_cell::sync::Lazy;
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize, Serializer};
 use std::collections::{BTreeMap, HashMap};
-use std::ffi::OsStr;
-use std::fs::read_dir;
-use std::io::BufReader;
-use std::ops::Deref;
 use std::path::{Path, PathBuf};
-use std::{fs, io, iter};
-use tracing::{debug, error, info, span, trace, warn, Level};
-use tracing_subscriber::field::debug;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use std::{fmt, fs, io, iter};
+use tracing::{debug, info, trace, warn};
 use walkdir::{DirEntry, WalkDir};
 
-use crate::{constants, recipes, Preferences};
+use crate::constants::RECIPE_EXTENSIONS;
+use crate::error::{Error, ParseError, Result};
+use crate::{Preferences, SearchDirSettings};
+use ignore::IgnoreRules;
+
+mod data_type;
+mod ignore;
+pub mod overrides;
+pub mod substitution;
+pub mod trust;
+
+pub use data_type::PlistDataType;
 
 /// Recipes are AutoPkg's primary object
-#[derive(Debug, Deserialize)]
+///
+/// Fields are declared in alphabetical order, which is the order they're
+/// written in, the same as Python AutoPkg.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Recipe {
+    // Overrides usually only contain an Identifier, Input, ParentRecipe and
+    // trust info, so everything else falls back to its default when missing,
+    // and isn't written back out when empty.
     /// Human-readable description of the recipe
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub description: String,
     /// Unique identifier for recipe
     pub identifier: String,
+    /// Input variables that can be overridden, in the order they're in the
+    /// file, which is the order they're substituted in
+    #[serde(
+        default,
+        skip_serializing_if = "IndexMap::is_empty",
+        serialize_with = "sorted_map"
+    )]
+    pub input: IndexMap<String, PlistDataType>,
     /// Minimum version of AutoPkg necessary to use recipe
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub minimum_version: String,
     /// Parent recipes are optional, such as in download recipes
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub parent_recipe: Option<String>,
-    /// Input variables that can be overridden
-    pub input: HashMap<String, PlistDataType>,
-    /// A list of Processors to execute in serial
-    pub process: Vec<Processor>,
     /// Trust info (only present in Overrides!)
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub parent_recipe_trust_info: Option<ParentRecipeTrust>,
+    /// A list of Processors to execute in serial
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub process: Vec<Processor>,
+    /// Path the recipe was loaded from, which is never part of the file
+    #[serde(skip)]
+    pub recipe_path: Option<PathBuf>,
+    /// Paths of all parent recipes merged into this one, nearest parent first
+    #[serde(skip)]
+    pub parent_recipes: Vec<PathBuf>,
 }
 
 impl Recipe {
@@ -53,12 +86,14 @@ impl Recipe {
             identifier,
             minimum_version,
             parent_recipe,
-            input: HashMap::from([(
+            input: IndexMap::from([(
                 "NAME".to_string(),
                 PlistDataType::Str("test_recipe".to_string()),
             )]),
             process: vec![initial_processor],
             parent_recipe_trust_info: None,
+            recipe_path: None,
+            parent_recipes: Vec::new(),
         }
     }
     pub fn has_parent(&self) -> bool {
@@ -82,158 +117,295 @@ impl Recipe {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct ParentRecipeTrust {
     /// Non-core processors by identifier/path
-    non_core_processors: HashMap<String, TrustBlock>,
+    pub non_core_processors: BTreeMap<String, TrustBlock>,
     /// All parents by identifier
-    parent_recipes: HashMap<String, TrustBlock>,
+    pub parent_recipes: BTreeMap<String, TrustBlock>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TrustBlock {
-    git_hash: String,
-    path: String,
-    sha256_hash: String,
-}
-
-#[derive(Debug)]
-pub struct UnreadableFileError;
-
-/// Plists (and yaml) can contain only limited possible values
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(untagged)]
-pub enum PlistDataType {
-    ArrayOfDicts(Vec<HashMap<String, String>>),
-    ArrayOfStrs(Vec<String>),
-    Bool(bool),
-    DictOfDicts(HashMap<String, PlistDataType>),
-    DictOfStrs(HashMap<String, String>),
-    Str(String),
+    /// Only present if the file was inside a git repo when trust was stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_hash: Option<String>,
+    pub path: String,
+    pub sha256_hash: String,
 }
 
 /// Processors all contain a processor name, and potentially arguments, which is a dictionary of PlistDataTypes
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Processor {
-    processor: String,
-    arguments: Option<HashMap<String, PlistDataType>>,
+    // Arguments sort before Processor, so that's the order they're written in
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "sorted_optional_map"
+    )]
+    pub arguments: Option<IndexMap<String, PlistDataType>>,
+    pub processor: String,
+}
+
+/// Serialize a map with its keys in sorted order, so that writing the same
+/// recipe twice always gives the same file
+fn sorted_map<S: Serializer, V: Serialize>(
+    map: &IndexMap<String, V>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+/// Serialize an optional map with its keys in sorted order
+fn sorted_optional_map<S: Serializer, V: Serialize>(
+    map: &Option<IndexMap<String, V>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    map.as_ref()
+        .map(|map| map.iter().collect::<BTreeMap<_, _>>())
+        .serialize(serializer)
+}
+
+/// The file formats recipes can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecipeFormat {
+    Plist,
+    Yaml,
+}
+
+/// Write any recipe data, such as a whole recipe or an override, to a file.
+///
+/// Keys are written in the order they're serialized, and plists are written
+/// as XML so they stay readable.
+pub fn write_recipe_data<T: Serialize>(path: &Path, data: &T, format: RecipeFormat) -> Result<()> {
+    let mut output = Vec::new();
+    match format {
+        RecipeFormat::Plist => {
+            plist::to_writer_xml(&mut output, data).map_err(|e| Error::parse(path, e))?;
+            // Python's plistlib ends the file with a newline
+            output.push(b'\n');
+        }
+        RecipeFormat::Yaml => {
+            serde_yaml::to_writer(&mut output, data).map_err(|e| Error::parse(path, e))?
+        }
+    }
+    fs::write(path, output).map_err(|e| Error::io(path, e))
 }
 
 /// Set a shorthand for RecipeMap for a sorted btreemap
-type RecipeMap = BTreeMap<String, BTreeMap<String, String>>;
+pub type RecipeMap = BTreeMap<String, BTreeMap<String, String>>;
 
 /// Read in the path with a plist parser
 ///
 /// The plist parser isn't very good, and will blow up if you look at it funny
 /// This is probably going to fail way more often than we'd expect
-pub fn read_plist_recipe(plist_path: &Path) -> Result<Recipe, plist::Error> {
+pub fn read_plist_recipe(plist_path: &Path) -> Result<Recipe> {
     trace!("Attempting to parse plist at {}", plist_path.display());
-    let plist_recipe: Recipe = plist::from_file(plist_path)?;
-    Ok(plist_recipe)
+    plist::from_file(plist_path).map_err(|e| Error::parse(plist_path, e))
 }
 
 /// Read in the path with a yaml parser
-pub fn read_yaml_recipe(yaml_path: &Path) -> Result<Recipe, serde_yaml::Error> {
+pub fn read_yaml_recipe(yaml_path: &Path) -> Result<Recipe> {
     trace!("Attempting to parse yaml at {}", yaml_path.display());
-    let f = fs::File::open(yaml_path).expect("Could not open file!");
-    let recipe: Recipe = serde_yaml::from_reader(f)?;
-    Ok(recipe)
+    let f = fs::File::open(yaml_path).map_err(|e| Error::io(yaml_path, e))?;
+    serde_yaml::from_reader(f).map_err(|e| Error::parse(yaml_path, e))
 }
 
-/// Attempt to read in the recipe at path, but if it fails, return
-/// a custom error struct UnreadableFileError
-pub fn read_recipe(path: &Path) -> Result<Recipe, UnreadableFileError> {
-    trace!("Loading {} - trying plist first", path.display());
-    read_plist_recipe(path)
-        .or_else(|err| read_yaml_recipe(path))
-        .map_err(|_| UnreadableFileError)
+/// Pick the format of a recipe file from its extension.
+///
+/// `.recipe.yaml` is yaml, while `.recipe.plist` and plain `.recipe` are
+/// plists, the same as Python AutoPkg. Returns None for any other file name.
+pub fn recipe_format(path: &Path) -> Option<RecipeFormat> {
+    match recipe_extension(path)? {
+        ".recipe.yaml" => Some(RecipeFormat::Yaml),
+        _ => Some(RecipeFormat::Plist),
+    }
 }
 
-fn find_parent(recipe: &Recipe, prefs: &Preferences) -> Option<Recipe> {
-    if let Some(parent) = &recipe.parent_recipe {
-        debug!("Found parent: {parent}");
-        let _ = read_recipe(&get_recipe_path_by_identifier(&recipe.identifier, prefs));
-    };
-    None
+/// Return the recipe extension a file name ends with, if any
+fn recipe_extension(path: &Path) -> Option<&'static str> {
+    let file_name = path.file_name()?.to_str()?;
+    RECIPE_EXTENSIONS
+        .into_iter()
+        .find(|extension| file_name.ends_with(extension))
 }
 
-/// Take a Recipe and return its parent identifier
-fn get_parent_identifier(recipe: &Recipe) -> Option<String> {
-    recipe.parent_recipe.to_owned()
+/// Read in the data of a recipe file, using the parser for its extension.
+///
+/// A file without a recipe extension is tried as a plist, and then as yaml.
+/// If neither parser can read it, both parser errors are kept in the
+/// returned Error::Parse.
+fn read_recipe_data<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let recipe_data = fs::read(path).map_err(|e| Error::io(path, e))?;
+    match recipe_format(path) {
+        Some(RecipeFormat::Plist) => {
+            trace!("Loading {} as a plist", path.display());
+            plist::from_bytes(&recipe_data).map_err(|e| Error::parse(path, e))
+        }
+        Some(RecipeFormat::Yaml) => {
+            trace!("Loading {} as yaml", path.display());
+            serde_yaml::from_slice(&recipe_data).map_err(|e| Error::parse(path, e))
+        }
+        None => {
+            trace!("Loading {} - trying plist first", path.display());
+            let plist_error = match plist::from_bytes(&recipe_data) {
+                Ok(data) => return Ok(data),
+                Err(plist_error) => plist_error,
+            };
+            serde_yaml::from_slice(&recipe_data).map_err(|yaml_error| {
+                Error::parse(
+                    path,
+                    ParseError::PlistOrYaml {
+                        plist: plist_error,
+                        yaml: yaml_error,
+                    },
+                )
+            })
+        }
+    }
+}
+
+/// Read in the recipe at path, using the parser for its extension
+pub fn read_recipe(path: &Path) -> Result<Recipe> {
+    read_recipe_data(path)
 }
 
-/// Take an identifier and return its parent identifier
-fn get_parent_identifier_from_id(id: &str, prefs: &Preferences) -> Option<String> {
-    let recipe_path = get_recipe_path_by_identifier(id, prefs);
-    let recipe = read_recipe(&recipe_path).unwrap();
-    recipe.parent_recipe
+/// Take a Recipe and return its parent identifier, if it has a usable one
+fn get_parent_identifier(recipe: &Recipe) -> Option<String> {
+    recipe
+        .parent_recipe
+        .to_owned()
+        .filter(|parent| !parent.is_empty())
 }
 
-/// Get the identifier of a parent recipe from disk.
+/// Compare two dotted version strings, returning true if `this` is equal to
+/// or greater than `that`.
 ///
-/// This takes a recipe path and reads the file in, and will panic if it
-/// cannot read the file.
-fn get_recipe_parent_identifier_from_path(
-    recipe_path: &str,
-    prefs: &Preferences,
-) -> Option<String> {
-    let recipe_id = "com.github.autopkg.install.AutoPkg-Release";
+/// Missing components are treated as 0, so "2.3" is equal to "2.3.0". Any
+/// non-numeric component compares as 0, which is close enough to Python's
+/// LooseVersion for the MinimumVersion strings found in recipes.
+pub fn version_equal_or_greater(this: &str, that: &str) -> bool {
+    let parse = |version: &str| -> Vec<u64> {
+        version
+            .split('.')
+            .map(|part| part.trim().parse().unwrap_or(0))
+            .collect()
+    };
+    let (mut this, mut that) = (parse(this), parse(that));
+    let length = this.len().max(that.len());
+    this.resize(length, 0);
+    that.resize(length, 0);
+    this >= that
+}
 
-    let recipe_path = get_recipe_path_by_identifier(recipe_id, prefs);
-    info!("Path: {}", recipe_path.display());
+/// Walk the ParentRecipe links of a recipe through the recipe map.
+///
+/// The starting recipe may be given by identifier or shortname, but parents
+/// are always looked up by identifier. The returned Vec is ordered from the
+/// top-most parent down to the requested recipe, which is the order the
+/// recipes must be merged in.
+fn load_recipe_chain(id: &str, recipe_map: &RecipeMap) -> Result<Vec<Recipe>> {
+    let mut recipe_path = find_recipe_in_map(recipe_map, id)
+        .map(PathBuf::from)
+        .ok_or_else(|| Error::RecipeNotFound(id.to_owned()))?;
+    let mut chain: Vec<Recipe> = Vec::new();
+    let mut identifier_chain: Vec<String> = Vec::new();
+    loop {
+        let mut recipe = read_recipe(&recipe_path)?;
+        recipe.recipe_path = Some(recipe_path.to_owned());
+        // A recipe we've already seen means the ParentRecipe links loop back
+        // on themselves, and we'd never reach the top
+        if identifier_chain.contains(&recipe.identifier) {
+            identifier_chain.push(recipe.identifier);
+            return Err(Error::CyclicParent(identifier_chain));
+        }
+        debug!("Pushing {} onto pile", recipe.identifier);
+        identifier_chain.push(recipe.identifier.to_owned());
+        let parent_id = get_parent_identifier(&recipe);
+        chain.push(recipe);
+        let Some(parent_id) = parent_id else {
+            break; // We've reached the top-most parent
+        };
+        recipe_path = recipe_map
+            .get("identifiers")
+            .and_then(|identifiers| identifiers.get(&parent_id))
+            .map(PathBuf::from)
+            .ok_or_else(|| Error::MissingParent {
+                child: identifier_chain.last().unwrap().to_owned(),
+                parent: parent_id,
+            })?;
+    }
+    debug!("Ids in the chain: {:?}", identifier_chain);
+    chain.reverse();
+    Ok(chain)
+}
 
-    let recipe = match read_recipe(&recipe_path) {
-        Ok(recipe) => recipe,
-        Err(e) => panic!("Unable to read recipe!"),
-    };
-    recipe.parent_recipe
+/// Flatten a chain of recipes, ordered from top-most parent to child, into a
+/// single Recipe.
+///
+/// This follows the same rules as Python AutoPkg:
+/// - Identifier, ParentRecipe and trust info come from the childmost recipe
+/// - Description comes from the childmost recipe that defines one
+/// - Input keys are merged, with the childmost value winning
+/// - MinimumVersion is the highest of all recipes in the chain
+/// - Process arrays are concatenated from top parent to child
+fn merge_recipe_chain(chain: Vec<Recipe>) -> Option<Recipe> {
+    let mut chain = chain.into_iter();
+    let mut merged = chain.next()?;
+    for child in chain {
+        // Each time we merge in a child, the previous recipe becomes its
+        // nearest parent
+        if let Some(parent_path) = merged.recipe_path.take() {
+            merged.parent_recipes.insert(0, parent_path);
+        }
+        merged.recipe_path = child.recipe_path;
+        merged.identifier = child.identifier;
+        if !child.description.is_empty() {
+            merged.description = child.description;
+        }
+        if version_equal_or_greater(&child.minimum_version, &merged.minimum_version) {
+            merged.minimum_version = child.minimum_version;
+        }
+        merged.parent_recipe = child.parent_recipe;
+        merged.input.extend(child.input);
+        merged.process.extend(child.process);
+        merged.parent_recipe_trust_info = child.parent_recipe_trust_info;
+    }
+    Some(merged)
 }
 
-pub fn load_recipe(id: &str, prefs: &Preferences) -> Recipe {
+/// Load a recipe by identifier or shortname, along with all of its parents,
+/// and return the combined Recipe.
+///
+/// Errors if the recipe or any of its parents can't be
+/// found or read, or if the parent chain is cyclic.
+pub fn load_recipe(id: &str, prefs: &Preferences) -> Result<Recipe> {
     trace!("Loading identifier at {id}");
-    // This should take a path and load up its parents
-    // 1. If it has a parent, follow that to the parent
-    // 2. Load the parent recipes into a Vec of Recipes
-    // 3. Merge the inputs together, first in, first out
-    // meaning, the childmost recipe should have the final say on values of keys;
-    // any keys defined in parents will just persist through. Last one always wins.
-    // 4. Combine the Processes together, from first (top parent) to last (child)
-    // 5. Return the combined Recipe
-    let id_path = get_recipe_path_by_identifier(id, prefs);
-    let recipe: Recipe = read_recipe(&id_path).unwrap();
-    // What if, instead of reading each recipe as I load its parents, I instead create
-    // a list of identifiers for all the parents, and then iterate through the vec
-    // and load each recipe?
-    // That seems much easier
-    debug!("Pushing starting recipe onto pile");
-    let parent_id: String = match get_parent_identifier(&recipe) {
-        Some(parent_id) => parent_id,
-        None => return recipe, // if there's no parent, just return this recipe
-    };
-    let mut identifier_chain: Vec<String> = vec![id.to_owned()];
-    while let Some(parent_id) = get_parent_identifier(&recipe) {
-        debug!("Pushing parent {parent_id} onto pile");
-        identifier_chain.push(parent_id);
-        let id_path = get_recipe_path_by_identifier(id, prefs);
-        let recipe = read_recipe(&id_path).unwrap();
-    }
-    debug!("Ids in the vec: {:?}", identifier_chain);
-    // TODO: This is fake for now to satisfy the build:
-    recipe
+    let recipe_map = read_recipe_map(prefs)?;
+    load_recipe_from_map(id, &recipe_map)
+}
+
+/// Load a recipe the same way as load_recipe, but skip any overrides, so
+/// the recipe and its parents all come from the search dirs
+pub fn load_recipe_ignoring_overrides(id: &str, prefs: &Preferences) -> Result<Recipe> {
+    trace!("Loading identifier at {id}, ignoring overrides");
+    let mut recipe_map = RecipeMap::clone(&*read_recipe_map(prefs)?);
+    recipe_map.remove("overrides");
+    load_recipe_from_map(id, &recipe_map)
+}
+
+fn load_recipe_from_map(id: &str, recipe_map: &RecipeMap) -> Result<Recipe> {
+    let chain = load_recipe_chain(id, recipe_map)?;
+    // The chain always contains at least the starting recipe
+    Ok(merge_recipe_chain(chain).unwrap())
 }
 
 /// This takes a DirEntry reference from a Walkdir walker
-/// and returns true if the filename ends with ".recipe"
-///
-/// TODO: Make this generic
+/// and returns true if the filename ends with any recipe extension
 fn is_recipe_file(entry: &DirEntry) -> bool {
     trace!("file_is_recipe: {:?}", &entry.file_name());
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| s.ends_with(".recipe"))
-        .unwrap_or(false)
+    recipe_extension(entry.path()).is_some()
 }
 
 /// This takes a DirEntry reference from a Walkdir walker
@@ -247,32 +419,59 @@ fn is_git_folder(entry: &DirEntry) -> bool {
         .unwrap_or(false)
 }
 
-/// Get all .recipe files recursively from a folder
+/// Get all recipe files recursively from a folder
 ///
-/// Note that this only goes two additional folder depth, so it's intended to
-/// search the Recipes dir, which could contain recipe files directly, or
+/// By default this only goes two additional folder depth, so it's intended
+/// to search the Recipes dir, which could contain recipe files directly, or
 /// folders therein. The RecipeRepos dir will contain repo folders, which
-/// themselves can contain subfolders for recipes.
+/// themselves can contain subfolders for recipes. Deeper layouts can raise
+/// the depth in the folder's settings, which can also skip files and
+/// folders by pattern.
 ///
-/// This will return a vector of paths to each of the recipes it finds.
-fn get_all_recipes_recursively_from_folder<P>(path: &P) -> Vec<PathBuf>
+/// This will return a vector of paths to each of the recipes it finds. The
+/// modification time of every folder that's searched is added to `folders`,
+/// and of every .autopkgignore file that's read to `ignore_files`, so we can
+/// tell later whether any recipes were added, removed or ignored.
+fn get_all_recipes_recursively_from_folder<P>(
+    path: &P,
+    settings: &SearchDirSettings,
+    folders: &mut BTreeMap<PathBuf, Option<SystemTime>>,
+    ignore_files: &mut BTreeMap<PathBuf, Option<SystemTime>>,
+) -> Vec<PathBuf>
 where
     P: AsRef<Path>, // This function takes any type that implements AsRef<Path>
                     // which could be a PathBuf or Path
 {
     let mut recipe_files = Vec::new();
+    let mut ignore_rules = IgnoreRules::new(path.as_ref(), settings);
+    // A search dir that doesn't exist yet is recorded too, so creating it
+    // makes the map stale
+    folders.insert(path.as_ref().to_path_buf(), folder_modified(path.as_ref()));
     let walker = WalkDir::new(path)
         .follow_links(true)
         .min_depth(1)
-        .max_depth(3)
+        .max_depth(settings.max_depth)
+        // Sorting keeps the first recipe to claim a name the same on every run
+        .sort_by_file_name()
         .into_iter()
-        .filter_entry(|f| !is_git_folder(f)); // don't bother looking inside .git folder
+        // don't bother looking inside .git folder, or anything ignored
+        .filter_entry(|f| !is_git_folder(f) && !ignore_rules.is_ignored(f.path()));
     for entry in walker.filter_map(|f| f.ok()) {
-        if is_recipe_file(&entry) {
+        // Folders at the maximum depth aren't searched, so their contents
+        // don't matter
+        if entry.file_type().is_dir() && entry.depth() < settings.max_depth {
+            folders.insert(entry.path().to_path_buf(), folder_modified(entry.path()));
+        } else if is_recipe_file(&entry) {
             trace!("{}", entry.path().display());
             recipe_files.push(entry.path().to_path_buf())
         }
     }
+    ignore_files.extend(
+        ignore_rules
+            .read_files()
+            .iter()
+            .map(|(path, modified)| (path.to_owned(), *modified)),
+    );
     // Alternative way of doing this:
     // .filter_map(|f| {
     //     match f {
@@ -286,78 +485,359 @@ where
     recipe_files
 }
 
-/// Read a plist file and return a specific String value
+/// Read a recipe file and return a specific String value
 ///
-/// This will fail if the key being asked for isn't a String.
-/// This function probably needs to be totally rewritten to support Results.
-fn get_key_from_recipe_file(recipe: &Path, key: &str) -> String {
-    let recipe_data = Value::from_file(recipe).expect("failed to read plist");
+/// This will fail if the file can't be parsed, or if the key being asked for
+/// isn't a String.
+fn get_key_from_recipe_file(recipe: &Path, key: &str) -> Result<String> {
+    let recipe_data: PlistDataType = read_recipe_data(recipe)?;
     get_string_key_from_recipe_value(&recipe_data, key)
+        .ok_or_else(|| Error::parse(recipe, ParseError::MissingKey(key.to_string())))
 }
 
 /// Return the String value of a specific key name from a recipe represented
-/// as a Plist::Value.
+/// as a PlistDataType.
 ///
-/// This probably needs to be made more generic.
-fn get_string_key_from_recipe_value(recipe_data: &Value, key: &str) -> String {
+/// Returns None if the key is missing or isn't a String.
+fn get_string_key_from_recipe_value(recipe_data: &PlistDataType, key: &str) -> Option<String> {
     let identifier = recipe_data
-        .as_dictionary()
+        .as_dict()
         .and_then(|dict| dict.get(key))
-        .and_then(|identifier| identifier.as_string())
-        .unwrap();
+        .and_then(|identifier| identifier.as_str())?;
     trace!("{}: {}", key, identifier);
-    identifier.to_string()
+    Some(identifier.to_string())
 }
 
 /// Given a specific recipe file path, return the "short name" of the recipe
 ///
 /// For example:
 /// `RecipeRepos/nmcspadden-recipes/Something/Something.download.recipe` ->
-/// `Something.download`
-fn calculate_short_name(entry: &Path) -> String {
-    let no_ext = entry.file_stem().unwrap();
+/// `Something.download`, and the same for `Something.download.recipe.yaml`
+///
+/// Returns None if the file name isn't valid UTF-8.
+fn calculate_short_name(entry: &Path) -> Option<String> {
+    let file_name = entry.file_name()?.to_str()?;
+    let no_ext = match recipe_extension(entry) {
+        Some(extension) => file_name.strip_suffix(extension)?,
+        None => entry.file_stem()?.to_str()?,
+    };
     trace!("Stem: {:?}", no_ext);
-    no_ext.to_owned().into_string().unwrap()
+    Some(no_ext.to_string())
 }
 
-fn build_maps_from_folder(
+/// Return the modification time of a folder or file, or None if it doesn't
+/// exist
+fn folder_modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The identifier of a recipe file, along with the size and modification
+/// time it had when the identifier was read
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct RecipeFileInfo {
+    identifier: String,
+    modified: SystemTime,
+    size: u64,
+}
+
+/// Whether a recipe file's size or modification time is different from
+/// when its identifier was read, or it's gone
+fn recipe_file_changed(recipe: &Path, info: &RecipeFileInfo) -> bool {
+    match fs::metadata(recipe) {
+        Ok(metadata) => {
+            metadata.len() != info.size || metadata.modified().ok() != Some(info.modified)
+        }
+        Err(_) => true,
+    }
+}
+
+/// Everything stored in the recipe map file.
+///
+/// Python AutoPkg only reads the identifiers, overrides and shortnames, so
+/// the extra keys we use to rebuild the map incrementally don't bother it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RecipeMapFile {
+    #[serde(flatten)]
+    map: RecipeMap,
+    /// Every folder the map was built from, in search order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    search_dirs: Vec<PathBuf>,
+    /// The settings the folders were searched with
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    search_dir_settings: BTreeMap<PathBuf, SearchDirSettings>,
+    /// Modification times of every folder that was searched for recipes
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    folders: BTreeMap<PathBuf, Option<SystemTime>>,
+    /// Modification times of every .autopkgignore file that was read
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    ignore_files: BTreeMap<PathBuf, Option<SystemTime>>,
+    /// What we know about every recipe file, by path
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    recipe_files: BTreeMap<String, RecipeFileInfo>,
+}
+
+impl RecipeMapFile {
+    /// Read the recipe map file, without checking whether it's up to date
+    fn read(path: &Path) -> Result<RecipeMapFile> {
+        // Reading the file into a string first is significantly faster than
+        // reading directly from a reader: https://github.com/serde-rs/json/issues/160
+        let json_data = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        // The BTreeMap here is strongly typed, so it force converts all the JSON data to
+        // the expected String types
+        serde_json::from_str(&json_data).map_err(|e| Error::parse(path, e))
+    }
+
+    /// Whether recipes may have been added, removed or edited since the map
+    /// was built.
+    ///
+    /// That's the case when the search dirs in the preferences, or their
+    /// settings, are different, or when any folder that was searched, any
+    /// .autopkgignore file that was read, or any recipe file has been
+    /// modified. A map without any search dirs was written by Python AutoPkg,
+    /// or by hand, so we trust it the same way Python AutoPkg would.
+    fn is_stale(&self, prefs: &Preferences) -> bool {
+        if self.search_dirs.is_empty() {
+            return false;
+        }
+        self.search_dirs != map_search_dirs(prefs)
+            || self.search_dir_settings != prefs.recipe_search_dir_settings
+            || self
+                .folders
+                .iter()
+                .chain(&self.ignore_files)
+                .any(|(path, modified)| folder_modified(path) != *modified)
+            || self
+                .recipe_files
+                .iter()
+                .any(|(path, info)| recipe_file_changed(Path::new(path), info))
+    }
+}
+
+/// Every folder recipes are found in, in search order: the search dirs, the
+/// recipe repo parent folder, and last of all the override folder
+fn map_search_dirs(prefs: &Preferences) -> Vec<PathBuf> {
+    prefs
+        .recipe_search_dirs
+        .iter()
+        .chain(iter::once(&prefs.recipe_repo_dir))
+        .chain(iter::once(&prefs.recipe_override_dir))
+        .cloned()
+        .collect()
+}
+
+/// A recipe map that's already been read or built by this process, along
+/// with the search dirs it was used for
+struct CachedRecipeMap {
+    search_dirs: Vec<PathBuf>,
+    search_dir_settings: BTreeMap<PathBuf, SearchDirSettings>,
+    map: Arc<RecipeMap>,
+}
+
+/// Recipe maps that have already been read or built by this process, by the
+/// path of the map file
+static RECIPE_MAP_CACHE: Lazy<Mutex<HashMap<PathBuf, CachedRecipeMap>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Remember a recipe map for the rest of the process
+fn cache_recipe_map(prefs: &Preferences, map: &Arc<RecipeMap>) {
+    RECIPE_MAP_CACHE.lock().unwrap().insert(
+        prefs.recipe_map_path.to_owned(),
+        CachedRecipeMap {
+            search_dirs: map_search_dirs(prefs),
+            search_dir_settings: prefs.recipe_search_dir_settings.to_owned(),
+            map: Arc::clone(map),
+        },
+    );
+}
+
+/// Return the recipe map this process already has for the preferences, unless
+/// it was for different search dirs or settings
+fn cached_recipe_map(prefs: &Preferences) -> Option<Arc<RecipeMap>> {
+    let cache = RECIPE_MAP_CACHE.lock().unwrap();
+    let cached = cache.get(&prefs.recipe_map_path)?;
+    (cached.search_dirs == map_search_dirs(prefs)
+        && cached.search_dir_settings == prefs.recipe_search_dir_settings)
+        .then(|| Arc::clone(&cached.map))
+}
+
+/// Everything found by searching one folder for recipes
+#[derive(Default)]
+struct FolderScan {
+    /// Identifier, shortname and path of every readable recipe, in path order
+    recipe_names: Vec<(String, String, String)>,
+    recipe_files: BTreeMap<String, RecipeFileInfo>,
+    folders: BTreeMap<PathBuf, Option<SystemTime>>,
+    ignore_files: BTreeMap<PathBuf, Option<SystemTime>>,
+}
+
+/// Return the identifier, size and modification time of a recipe file. The
+/// file is only read if its size or modification time changed since the
+/// `previous` info was recorded.
+fn read_recipe_file_info(
+    recipe: &Path,
+    previous: Option<&RecipeFileInfo>,
+) -> Result<RecipeFileInfo> {
+    let metadata = fs::metadata(recipe).map_err(|e| Error::io(recipe, e))?;
+    let modified = metadata.modified().map_err(|e| Error::io(recipe, e))?;
+    let size = metadata.len();
+    let identifier = match previous {
+        Some(info) if info.modified == modified && info.size == size => {
+            trace!("Unchanged since the last build: {}", recipe.display());
+            info.identifier.to_owned()
+        }
+        _ => get_key_from_recipe_file(recipe, "Identifier")?,
+    };
+    Ok(RecipeFileInfo {
+        identifier,
+        modified,
+        size,
+    })
+}
+
+/// Find every recipe in a folder, reusing the identifiers in `previous` for
+/// recipe files that haven't changed.
+///
+/// Recipe files are read in parallel, but are always returned in path order.
+fn get_recipe_names_from_folder(
     expanded_path: &Path,
-    identifier_map: &mut BTreeMap<String, String>,
-    shortname_map: &mut BTreeMap<String, String>,
-) {
+    settings: &SearchDirSettings,
+    previous: &BTreeMap<String, RecipeFileInfo>,
+) -> FolderScan {
     info!("Considering looking through {}", expanded_path.display());
 
-    let recipes_in_folder = get_all_recipes_recursively_from_folder(&expanded_path);
+    let mut scan = FolderScan::default();
+    let recipes_in_folder = get_all_recipes_recursively_from_folder(
+        &expanded_path,
+        settings,
+        &mut scan.folders,
+        &mut scan.ignore_files,
+    );
     info!("Calculating identifiers and shortnames");
-    for recipe in recipes_in_folder {
-        trace!("Recipe: {}", recipe.display());
-        let identifier = get_key_from_recipe_file(&recipe, "Identifier");
-        let shortname = calculate_short_name(&recipe);
-
-        // We must convert the recipe PathBuf here into a String, and so we
-        // shadow the 'recipe' variable name since we don't need its original value anymore
-        let recipe = recipe.into_os_string().into_string().unwrap();
+    let recipes: Vec<(String, String, RecipeFileInfo)> = recipes_in_folder
+        .par_iter()
+        .filter_map(|recipe| {
+            trace!("Recipe: {}", recipe.display());
+            // We must convert the recipe PathBuf here into a String
+            let (Some(shortname), Some(recipe_key)) =
+                (calculate_short_name(recipe), recipe.to_str())
+            else {
+                warn!("Skipping recipe with non-UTF-8 path: {}", recipe.display());
+                return None;
+            };
+            // A single unreadable recipe shouldn't stop the whole map from being
+            // built, so we skip it and keep going
+            match read_recipe_file_info(recipe, previous.get(recipe_key)) {
+                Ok(info) => Some((shortname, recipe_key.to_string(), info)),
+                Err(e) => {
+                    warn!("Skipping recipe: {e}");
+                    None
+                }
+            }
+        })
+        .collect();
+    for (shortname, recipe, info) in recipes {
+        scan.recipe_names
+            .push((info.identifier.to_owned(), shortname, recipe.to_owned()));
+        scan.recipe_files.insert(recipe, info);
+    }
+    scan
+}
+
+/// Two recipe files that claim the same name in one section of the recipe
+/// map. Only the recipe that was found first is in the map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecipeCollision {
+    /// The recipe map section: "identifiers", "overrides" or "shortnames"
+    pub section: &'static str,
+    /// The identifier, shortname or override name both recipes claim
+    pub name: String,
+    /// Path of the recipe that's in the map
+    pub kept: String,
+    /// Path of the recipe that's hidden by it
+    pub shadowed: String,
+}
+
+impl fmt::Display for RecipeCollision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.section {
+            "identifiers" => "identifier",
+            "shortnames" => "shortname",
+            _ => "override name",
+        };
+        write!(
+            f,
+            "Duplicate {kind} {}: using {}, ignoring {}",
+            self.name, self.kept, self.shadowed
+        )
+    }
+}
+
+/// Add a recipe to one section of the recipe map.
+///
+/// The first recipe to claim a name keeps it, so search dirs earlier in
+/// RECIPE_SEARCH_DIRS take precedence over later ones. Any later recipe with
+/// the same name is recorded as a collision instead.
+fn insert_recipe(
+    section: &'static str,
+    names: &mut BTreeMap<String, String>,
+    name: String,
+    recipe: String,
+    collisions: &mut Vec<RecipeCollision>,
+) {
+    match names.get(&name) {
+        None => {
+            names.insert(name, recipe);
+        }
+        // The same file can be found twice if one search dir is inside another
+        Some(kept) if *kept == recipe => {}
+        Some(kept) => {
+            debug!("{section}: {name} at {recipe} is shadowed by {kept}");
+            collisions.push(RecipeCollision {
+                section,
+                name,
+                kept: kept.to_owned(),
+                shadowed: recipe,
+            });
+        }
+    }
+}
+
+fn build_maps_from_folder(
+    recipe_names: Vec<(String, String, String)>,
+    identifier_map: &mut BTreeMap<String, String>,
+    shortname_map: &mut BTreeMap<String, String>,
+    collisions: &mut Vec<RecipeCollision>,
+) {
+    for (identifier, shortname, recipe) in recipe_names {
         // We have to clone it explicitly because otherwise we have an ownership collision
-        identifier_map.insert(identifier, recipe.clone());
-        shortname_map.insert(shortname, recipe);
+        insert_recipe(
+            "identifiers",
+            identifier_map,
+            identifier,
+            recipe.clone(),
+            collisions,
+        );
+        insert_recipe("shortnames", shortname_map, shortname, recipe, collisions);
     }
 }
 
-/// Get a list of all directories one level under a topdir
-fn list_dirs_within_folder(topdir: &Path) -> Result<Vec<PathBuf>> {
-    // Shout out to https://stackoverflow.com/a/67940478 for this
-    Ok(read_dir(topdir)?
-        .filter_map(|f| {
-            f.ok().and_then(|d| {
-                let p = d.path();
-                if p.is_dir() {
-                    Some(p)
-                } else {
-                    None
-                }
-            })
-        })
-        .collect())
+/// Add every override in a folder to the override map, by both its override
+/// name and its identifier
+fn build_override_map_from_folder(
+    recipe_names: Vec<(String, String, String)>,
+    override_map: &mut BTreeMap<String, String>,
+    collisions: &mut Vec<RecipeCollision>,
+) {
+    for (identifier, override_name, recipe) in recipe_names {
+        insert_recipe(
+            "overrides",
+            override_map,
+            identifier,
+            recipe.clone(),
+            collisions,
+        );
+        insert_recipe("overrides", override_map, override_name, recipe, collisions);
+    }
 }
 
 /// Build a recipe map of all known recipes.
@@ -368,110 +848,285 @@ fn list_dirs_within_folder(topdir: &Path) -> Result<Vec<PathBuf>> {
 ///     identifier: absolute file path
 ///   },
 ///   "overrides": {
-///     override_name: absolute file path
+///     override_name: absolute file path,
+///     override identifier: absolute file path
 ///   },
 ///   "shortnames": {
 ///     short_name: absolute file path
 ///   },
 /// }
-/// TODO: Add support for Overrides
-pub fn build_recipe_map(prefs: &Preferences) -> Result<RecipeMap, Box<dyn std::error::Error>> {
+///
+/// When more than one recipe claims the same name, the one in the earliest
+/// search dir wins, and every other one is returned as a collision. With
+/// `strict`, any collision is an error and the map isn't written to disk.
+///
+/// Only recipe files whose size or modification time changed since the map
+/// was last written are read again. Folders are searched in parallel, and
+/// the results are merged in search order, so the map is always the same as
+/// searching them one at a time.
+pub fn build_recipe_map(
+    prefs: &Preferences,
+    strict: bool,
+) -> Result<(Arc<RecipeMap>, Vec<RecipeCollision>)> {
     // We're using BTreeMaps here because they are always sorted by keys
     // This means the JSON representation of these will be sorted, and
     // deterministic
     let mut recipe_map: RecipeMap = BTreeMap::new();
     let mut identifier_map: BTreeMap<String, String> = BTreeMap::new();
     let mut shortname_map: BTreeMap<String, String> = BTreeMap::new();
+    let mut override_map: BTreeMap<String, String> = BTreeMap::new();
+    let mut recipe_files = BTreeMap::new();
+    let mut folders = BTreeMap::new();
+    let mut ignore_files = BTreeMap::new();
+    let mut collisions = Vec::new();
+    // Anything wrong with the previous map just means reading every recipe
+    let previous = RecipeMapFile::read(&prefs.recipe_map_path).unwrap_or_default();
 
-    // Look for recipes in the recipe repo parent folder first
-    // TODO: Iterate through the search dirs along with the recipe repo parent folder to look for recipes
+    // Look through the search dirs in order, and then the recipe repo parent
+    // folder, so recipes in earlier search dirs take precedence
     let dirs = prefs.recipe_search_dirs.iter();
     let repos = iter::once(&prefs.recipe_repo_dir);
-    let paths_to_search = dirs.chain(repos);
+    let paths_to_search: Vec<&PathBuf> = dirs.chain(repos).collect();
+    let (scans, override_scan) = rayon::join(
+        || {
+            paths_to_search
+                .par_iter()
+                .map(|folder| {
+                    let settings = prefs.search_dir_settings(folder);
+                    get_recipe_names_from_folder(folder, &settings, &previous.recipe_files)
+                })
+                .collect::<Vec<_>>()
+        },
+        || {
+            let override_dir = &prefs.recipe_override_dir;
+            let settings = prefs.search_dir_settings(override_dir);
+            get_recipe_names_from_folder(override_dir, &settings, &previous.recipe_files)
+        },
+    );
 
-    for folder in paths_to_search {
-        build_maps_from_folder(folder, &mut identifier_map, &mut shortname_map);
+    for scan in scans {
+        build_maps_from_folder(
+            scan.recipe_names,
+            &mut identifier_map,
+            &mut shortname_map,
+            &mut collisions,
+        );
+        recipe_files.extend(scan.recipe_files);
+        folders.extend(scan.folders);
+        ignore_files.extend(scan.ignore_files);
+    }
+    build_override_map_from_folder(
+        override_scan.recipe_names,
+        &mut override_map,
+        &mut collisions,
+    );
+    recipe_files.extend(override_scan.recipe_files);
+    folders.extend(override_scan.folders);
+    ignore_files.extend(override_scan.ignore_files);
+    if strict && !collisions.is_empty() {
+        return Err(Error::RecipeCollisions(collisions));
     }
 
     recipe_map.insert("identifiers".to_string(), identifier_map);
+    recipe_map.insert("overrides".to_string(), override_map);
     recipe_map.insert("shortnames".to_string(), shortname_map);
 
     // Emit to disk
     info!("Writing recipe map to disk at {:?}", prefs.recipe_map_path);
-    std::fs::write(
-        &prefs.recipe_map_path,
-        serde_json::to_string_pretty(&recipe_map).unwrap(),
-    )?;
+    let recipe_map_file = RecipeMapFile {
+        map: recipe_map,
+        search_dirs: map_search_dirs(prefs),
+        search_dir_settings: prefs.recipe_search_dir_settings.to_owned(),
+        folders,
+        ignore_files,
+        recipe_files,
+    };
+    let recipe_map_json = serde_json::to_string_pretty(&recipe_map_file)
+        .map_err(|e| Error::parse(&prefs.recipe_map_path, e))?;
+    fs::write(&prefs.recipe_map_path, recipe_map_json)
+        .map_err(|e| Error::io(&prefs.recipe_map_path, e))?;
 
+    let recipe_map = Arc::new(recipe_map_file.map);
+    cache_recipe_map(prefs, &recipe_map);
+    Ok((recipe_map, collisions))
+}
+
+/// Return the recipe map, reading it from its JSON file the first time.
+///
+/// If the file is missing, or recipes may have been added or removed since
+/// it was written, the map is rebuilt first. After that, the same map is
+/// returned for the rest of the process, as long as the search dirs and their
+/// settings stay the same.
+pub fn read_recipe_map(prefs: &Preferences) -> Result<Arc<RecipeMap>> {
+    if let Some(recipe_map) = cached_recipe_map(prefs) {
+        return Ok(recipe_map);
+    }
+
+    let recipe_map_file = match RecipeMapFile::read(&prefs.recipe_map_path) {
+        Ok(recipe_map_file) if !recipe_map_file.is_stale(prefs) => recipe_map_file,
+        Ok(_) => {
+            info!("Recipes or search dirs have changed, rebuilding the recipe map");
+            return rebuild_recipe_map(prefs);
+        }
+        Err(Error::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => {
+            info!("No recipe map found, building it");
+            return rebuild_recipe_map(prefs);
+        }
+        Err(e) => return Err(e),
+    };
+    let recipe_map = Arc::new(recipe_map_file.map);
+    cache_recipe_map(prefs, &recipe_map);
     Ok(recipe_map)
 }
 
-/// Read the recipe map from JSON file
-pub fn read_recipe_map(prefs: &Preferences) -> Result<RecipeMap> {
-    // Reading the file into a string first is significantly faster than
-    // reading directly from a reader: https://github.com/serde-rs/json/issues/160
-    let json_data = fs::read_to_string(&prefs.recipe_map_path)?;
-    // The BTreeMap here is strongly typed, so it force converts all the JSON data to
-    // the expected String types
-    let recipe_map: RecipeMap = serde_json::from_str(&json_data)?;
+/// Rebuild the recipe map when it's needed for a lookup, warning about
+/// collisions instead of failing
+fn rebuild_recipe_map(prefs: &Preferences) -> Result<Arc<RecipeMap>> {
+    let (recipe_map, collisions) = build_recipe_map(prefs, false)?;
+    for collision in collisions {
+        warn!("{collision}");
+    }
     Ok(recipe_map)
 }
 
+/// Find a recipe path in the map by override, identifier or shortname.
+///
+/// Like Python AutoPkg, overrides always win over recipes, and identifiers
+/// win over shortnames.
 pub fn find_recipe_in_map(map: &RecipeMap, recipe: &str) -> Option<String> {
     debug!("find_recipe_in_map: Recipe {recipe}");
-    map["identifiers"]
-        .get(recipe)
-        // .and(map["overrides"].get(recipe))
-        .or_else(|| map["shortnames"].get(recipe))
+    ["overrides", "identifiers", "shortnames"]
+        .iter()
+        .find_map(|section| map.get(*section).and_then(|names| names.get(recipe)))
         .cloned()
 }
 
-/// Generate an override for a recipe
-// pub fn generate_recipe_override(recipe: &Recipe) -> Recipe {
-pub fn generate_recipe_override(recipe: &Recipe) {
-    debug!("Generating override!");
-    // To generate an override, we now have to actually load the full recipe
-    // We need all the identifiers paths in the recipe chain,
-    // along with any non-core processors in order to correctly
-    // generate a hash
+/// A recipe in the recipe map, as listed by list-recipes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RecipeListing {
+    pub shortname: String,
+    pub identifier: String,
+    pub path: PathBuf,
+    /// Name of the recipe repo or search dir the recipe is in
+    pub repo: Option<String>,
+}
+
+/// Which recipes to list. An empty list lets every recipe through.
+#[derive(Debug, Default)]
+pub struct RecipeFilter {
+    /// Repo names, which match a repo folder by its whole name or by its
+    /// last dotted part, so `recipes` matches `com.github.autopkg.recipes`
+    pub repos: Vec<String>,
+    /// Recipe types, such as `download` or `.munki`, which match the end of
+    /// the shortname
+    pub types: Vec<String>,
+}
+
+impl RecipeFilter {
+    fn matches(&self, listing: &RecipeListing) -> bool {
+        let repo_matches = self.repos.is_empty()
+            || listing.repo.as_deref().is_some_and(|repo| {
+                self.repos
+                    .iter()
+                    .any(|wanted| repo == wanted || repo.ends_with(&format!(".{wanted}")))
+            });
+        let type_matches = self.types.is_empty()
+            || self.types.iter().any(|wanted| {
+                let suffix = format!(".{}", wanted.trim_start_matches('.'));
+                listing.shortname.ends_with(&suffix)
+            });
+        repo_matches && type_matches
+    }
+}
+
+/// List every recipe in the map that passes the filter, sorted by shortname
+pub fn list_recipes(
+    map: &RecipeMap,
+    prefs: &Preferences,
+    filter: &RecipeFilter,
+) -> Vec<RecipeListing> {
+    let mut listings: Vec<RecipeListing> = map
+        .get("identifiers")
+        .into_iter()
+        .flatten()
+        .filter_map(|(identifier, path)| {
+            let path = PathBuf::from(path);
+            Some(RecipeListing {
+                shortname: calculate_short_name(&path)?,
+                identifier: identifier.to_owned(),
+                repo: recipe_repo_name(&path, prefs),
+                path,
+            })
+        })
+        .filter(|listing| filter.matches(listing))
+        .collect();
+    listings.sort_by(|a, b| {
+        (a.shortname.to_lowercase(), &a.identifier)
+            .cmp(&(b.shortname.to_lowercase(), &b.identifier))
+    });
+    listings
+}
+
+/// Return the name of the repo a recipe is in: its folder in the recipe repo
+/// dir, or else the name of the closest search dir it's in
+fn recipe_repo_name(path: &Path, prefs: &Preferences) -> Option<String> {
+    let name = |name: &std::ffi::OsStr| name.to_string_lossy().into_owned();
+    if let Ok(relative) = path.strip_prefix(&prefs.recipe_repo_dir) {
+        if let Some(std::path::Component::Normal(repo)) = relative.components().next() {
+            if relative.components().count() > 1 {
+                return Some(name(repo));
+            }
+        }
+    }
+    prefs
+        .recipe_search_dirs
+        .iter()
+        .filter(|dir| path.starts_with(dir))
+        .max_by_key(|dir| dir.components().count())
+        .and_then(|dir| {
+            crate::prefs::normalize_search_dir(dir)
+                .file_name()
+                .map(name)
+        })
 }
 
 /// Find a recipe path by searching map for an identifier.
+pub fn get_recipe_path_by_identifier(identifier: &str, prefs: &Preferences) -> Result<PathBuf> {
+    let recipe_map = read_recipe_map(prefs)?;
+    recipe_map
+        .get("identifiers")
+        .and_then(|identifiers| identifiers.get(identifier))
+        .map(PathBuf::from)
+        .ok_or_else(|| Error::RecipeNotFound(identifier.to_string()))
+}
+
+/// Write a recipe map file from `(section, name, path)` entries
 ///
-/// Panics if recipe is not found in map. This should probably be rewritten
-/// to return a Result instead. This nested match is ugly.
-pub fn get_recipe_path_by_identifier(identifier: &str, prefs: &Preferences) -> PathBuf {
-    let recipe_map = match read_recipe_map(prefs) {
-        Ok(recipe_map) => recipe_map,
-        Err(e) => panic!("Unable to read recipe map: {}", e),
-    };
-    match recipe_map["identifiers"].get(identifier) {
-        Some(path) => PathBuf::from(path),
-        None => panic!("Identifier {identifier} not found in recipe map!"),
-    }
-}
-
-// I need to figure out how to get this to return a result correctly
-// pub fn get_recipe_path_by_identifier2(identifier: &str) -> Result<String> {
-//     // let recipe_map = match read_recipe_map() {
-//     //     Ok(recipe_map) => recipe_map,
-//     //     Err(e) => panic!("Unable to read recipe map: {}", e),
-//     // };
-//     // match recipe_map["identifiers"].get(identifier) {
-//     //     Some(path) => path.to_string(),
-//     //     None => panic!("Identifier {identifier} not found in recipe map!"),
-//     // }
-//     let recipe_map = read_recipe_map()?;
-//     recipe_map["identifiers"]
-//         .get(identifier)
-//         .ok_or("Identifier not found in recipe map")
-// }
+/// This is only used for tests, so they don't need to build the map by
+/// searching for recipes
+#[cfg(test)]
+pub(crate) fn write_test_recipe_map(prefs: &Preferences, entries: &[(&str, &str, &Path)]) {
+    let mut recipe_map = RecipeMap::new();
+    for (section, name, path) in entries {
+        recipe_map
+            .entry(section.to_string())
+            .or_default()
+            .insert(name.to_string(), path.display().to_string());
+    }
+    fs::write(
+        &prefs.recipe_map_path,
+        serde_json::to_string(&recipe_map).unwrap(),
+    )
+    .unwrap();
+}
 
 #[cfg(test)]
 mod tests {
+    use plist::Value;
     use std::io::Cursor;
 
     use super::*;
+    use crate::constants::AUTOPKGIGNORE_FILENAME;
 
     /// Create a default recipe structure with test data only
     ///
@@ -516,10 +1171,92 @@ mod tests {
         test_recipe.minimum_version = "".to_string();
         assert!(!test_recipe.is_valid_recipe());
         // Input must contain "NAME" key, if we delete it, invalid recipe
-        test_recipe.input.remove("NAME");
+        test_recipe.input.shift_remove("NAME");
         assert!(!test_recipe.is_valid_recipe());
     }
 
+    #[test]
+    fn test_recipe_round_trip() {
+        let sample_data = Path::new(env!("CARGO_MANIFEST_DIR")).join("sample_data");
+        let dir = tempfile::tempdir().unwrap();
+        for sample in [
+            "CitrixWorkspace.download.recipe",
+            "CitrixWorkspace.download.recipe.yaml",
+            "GoogleChromePkg.download.recipe",
+            "GoogleChrome_override.download.recipe",
+            "Tuple.download.recipe.yaml",
+            "Tuple.munki.recipe.yaml",
+        ] {
+            let recipe = read_recipe(&sample_data.join(sample)).unwrap();
+            let shortname = calculate_short_name(Path::new(sample)).unwrap();
+            for (format, extension) in [
+                (RecipeFormat::Plist, ".recipe.plist"),
+                (RecipeFormat::Yaml, ".recipe.yaml"),
+            ] {
+                let path = dir.path().join(format!("{sample}-{shortname}{extension}"));
+                write_recipe_data(&path, &recipe, format).unwrap();
+                assert_eq!(
+                    read_recipe(&path).unwrap(),
+                    recipe,
+                    "{sample} as {format:?}"
+                );
+                // Writing the same recipe again gives exactly the same file
+                let written = fs::read(&path).unwrap();
+                write_recipe_data(&path, &recipe, format).unwrap();
+                assert_eq!(fs::read(&path).unwrap(), written);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_recipe_with_any_input_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Test.download.recipe.yaml");
+        fs::write(
+            &path,
+            "Identifier: com.github.autopkg.download.Test\n\
+            Input:\n  NAME: Test\n  MAX_RETRIES: 3\n  TIMEOUT: 2.5\n  \
+            pkginfo:\n    requires:\n      - name: Other\n        versions: [1, '2']\n",
+        )
+        .unwrap();
+        let recipe = read_recipe(&path).unwrap();
+        assert_eq!(
+            recipe.input["MAX_RETRIES"],
+            PlistDataType::Integer(3.into())
+        );
+        assert_eq!(recipe.input["TIMEOUT"], PlistDataType::Real(2.5));
+        let requires = &recipe.input["pkginfo"].as_dict().unwrap()["requires"];
+        assert_eq!(
+            requires.as_array().unwrap()[0].as_dict().unwrap()["versions"],
+            PlistDataType::Array(vec![PlistDataType::Integer(1.into()), "2".into()])
+        );
+    }
+
+    #[test]
+    fn test_recipe_key_order() {
+        let sample_data = Path::new(env!("CARGO_MANIFEST_DIR")).join("sample_data");
+        let recipe = read_recipe(&sample_data.join("Tuple.munki.recipe.yaml")).unwrap();
+        let yaml = serde_yaml::to_string(&recipe).unwrap();
+        let keys: Vec<&str> = yaml
+            .lines()
+            .filter(|line| !line.starts_with([' ', '-']))
+            .filter_map(|line| line.split(':').next())
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                "Description",
+                "Identifier",
+                "Input",
+                "MinimumVersion",
+                "ParentRecipe",
+                "Process"
+            ]
+        );
+        // Keys within the Input are sorted too
+        assert!(yaml.contains("Input:\n  MUNKI_REPO_SUBDIR: apps/%NAME%\n  NAME: tuple\n"));
+    }
+
     #[test]
     fn test_get_string_key_from_recipe_value() {
         // Create a copy of GoogleChrome.download.recipe as a plist string
@@ -579,20 +1316,19 @@ mod tests {
         // This sets up a seekable reader from a string
         let seekable_plist = Cursor::new(plist_string);
         // Read in the value from our Cursor, which acts like reading from a file
-        let recipe_data = Value::from_reader(seekable_plist).unwrap();
+        let recipe_data = PlistDataType::from(Value::from_reader(seekable_plist).unwrap());
         // We should be able to extract the specific strings we want
         assert_eq!(
-            get_string_key_from_recipe_value(&recipe_data, "Identifier"),
-            "com.github.autopkg.download.googlechromepkg"
+            get_string_key_from_recipe_value(&recipe_data, "Identifier").as_deref(),
+            Some("com.github.autopkg.download.googlechromepkg")
         );
         assert_eq!(
-            get_string_key_from_recipe_value(&recipe_data, "MinimumVersion"),
-            "1.4.1"
+            get_string_key_from_recipe_value(&recipe_data, "MinimumVersion").as_deref(),
+            Some("1.4.1")
         );
     }
 
     #[test]
-    #[should_panic]
     fn test_get_string_key_from_recipe_value_should_fail() {
         // Do the same thing as the above test, except reading a non-string should fail
         let plist_string = "
@@ -649,17 +1385,634 @@ mod tests {
         </dict>
         </plist>";
         let seekable_plist = Cursor::new(plist_string);
-        let recipe_data = Value::from_reader(seekable_plist).unwrap();
+        let recipe_data = PlistDataType::from(Value::from_reader(seekable_plist).unwrap());
         // This only can parse strings, so pulling a non-string from the plist should
-        // panic
-        get_string_key_from_recipe_value(&recipe_data, "Process");
+        // return nothing
+        assert_eq!(
+            get_string_key_from_recipe_value(&recipe_data, "Process"),
+            None
+        );
+    }
+
+    /// Write a minimal yaml recipe into a folder and return its path
+    ///
+    /// This is only used for tests
+    fn write_test_recipe(
+        dir: &Path,
+        identifier: &str,
+        parent: Option<&str>,
+        input: &str,
+        processor: &str,
+    ) -> String {
+        let parent = parent
+            .map(|parent| format!("ParentRecipe: {parent}\n"))
+            .unwrap_or_default();
+        let recipe_yaml = format!(
+            "Description: {identifier}\n\
+            Identifier: {identifier}\n\
+            MinimumVersion: '2.3'\n\
+            {parent}\
+            Input:\n  NAME: {input}\n  {input}: {identifier}\n\
+            Process:\n  - Processor: {processor}\n"
+        );
+        let path = dir.join(format!("{identifier}.recipe.yaml"));
+        fs::write(&path, recipe_yaml).unwrap();
+        path.into_os_string().into_string().unwrap()
+    }
+
+    /// Build an in-memory recipe map containing only identifiers
+    fn create_test_recipe_map(identifiers: Vec<(&str, String)>) -> RecipeMap {
+        let identifiers = identifiers
+            .into_iter()
+            .map(|(id, path)| (id.to_string(), path))
+            .collect();
+        BTreeMap::from([
+            ("identifiers".to_string(), identifiers),
+            ("shortnames".to_string(), BTreeMap::new()),
+        ])
+    }
+
+    #[test]
+    fn test_load_recipe_chain_merges_parents() {
+        let dir = tempfile::tempdir().unwrap();
+        let map = create_test_recipe_map(vec![
+            (
+                "test.download",
+                write_test_recipe(
+                    dir.path(),
+                    "test.download",
+                    None,
+                    "Download",
+                    "URLDownloader",
+                ),
+            ),
+            (
+                "test.pkg",
+                write_test_recipe(
+                    dir.path(),
+                    "test.pkg",
+                    Some("test.download"),
+                    "Pkg",
+                    "PkgCreator",
+                ),
+            ),
+            (
+                "test.munki",
+                write_test_recipe(
+                    dir.path(),
+                    "test.munki",
+                    Some("test.pkg"),
+                    "Munki",
+                    "MunkiImporter",
+                ),
+            ),
+        ]);
+        let chain = load_recipe_chain("test.munki", &map).unwrap();
+        let identifiers: Vec<&str> = chain.iter().map(|r| r.identifier.as_str()).collect();
+        assert_eq!(identifiers, vec!["test.download", "test.pkg", "test.munki"]);
+
+        let recipe = merge_recipe_chain(chain).unwrap();
+        assert_eq!(recipe.identifier, "test.munki");
+        assert_eq!(recipe.parent_recipe, Some("test.pkg".to_string()));
+        // The childmost NAME wins, but keys only defined in parents persist
+        assert_eq!(
+            recipe.input["NAME"],
+            PlistDataType::Str("Munki".to_string())
+        );
+        assert_eq!(
+            recipe.input["Download"],
+            PlistDataType::Str("test.download".to_string())
+        );
+        assert_eq!(
+            recipe.recipe_path,
+            Some(dir.path().join("test.munki.recipe.yaml"))
+        );
+        assert_eq!(
+            recipe.parent_recipes,
+            vec![
+                dir.path().join("test.pkg.recipe.yaml"),
+                dir.path().join("test.download.recipe.yaml")
+            ]
+        );
+        let processors: Vec<&str> = recipe
+            .process
+            .iter()
+            .map(|p| p.processor.as_str())
+            .collect();
+        assert_eq!(
+            processors,
+            vec!["URLDownloader", "PkgCreator", "MunkiImporter"]
+        );
+    }
+
+    #[test]
+    fn test_load_recipe_chain_missing_parent() {
+        let dir = tempfile::tempdir().unwrap();
+        let map = create_test_recipe_map(vec![(
+            "test.pkg",
+            write_test_recipe(
+                dir.path(),
+                "test.pkg",
+                Some("test.download"),
+                "Pkg",
+                "PkgCreator",
+            ),
+        )]);
+        let err = load_recipe_chain("test.pkg", &map).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::MissingParent { child, parent }
+                if child == "test.pkg" && parent == "test.download"
+        ));
+        let err = load_recipe_chain("test.munki", &map).unwrap_err();
+        assert!(matches!(err, Error::RecipeNotFound(_)));
+    }
+
+    #[test]
+    fn test_load_recipe_chain_cyclic_parent() {
+        let dir = tempfile::tempdir().unwrap();
+        let map = create_test_recipe_map(vec![
+            (
+                "test.a",
+                write_test_recipe(dir.path(), "test.a", Some("test.b"), "A", "EndOfCheckPhase"),
+            ),
+            (
+                "test.b",
+                write_test_recipe(dir.path(), "test.b", Some("test.a"), "B", "EndOfCheckPhase"),
+            ),
+        ]);
+        let err = load_recipe_chain("test.a", &map).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::CyclicParent(chain) if chain == ["test.a", "test.b", "test.a"]
+        ));
+    }
+
+    #[test]
+    fn test_list_recipes() {
+        let dir = tempfile::tempdir().unwrap();
+        let repos = dir.path().join("RecipeRepos");
+        let autopkg = repos.join("com.github.autopkg.recipes");
+        let local = dir.path().join("Local");
+        let recipe = |folder: &Path, name: &str| folder.join(name).display().to_string();
+        let map = RecipeMap::from([(
+            "identifiers".to_string(),
+            BTreeMap::from([
+                (
+                    "com.github.autopkg.download.Firefox".to_string(),
+                    recipe(&autopkg, "Mozilla/Firefox.download.recipe"),
+                ),
+                (
+                    "com.github.autopkg.munki.Firefox".to_string(),
+                    recipe(&autopkg, "Mozilla/Firefox.munki.recipe"),
+                ),
+                (
+                    "local.pkg.Thing".to_string(),
+                    recipe(&local, "thing.pkg.recipe.yaml"),
+                ),
+            ]),
+        )]);
+        let mut prefs = Preferences::new();
+        prefs.recipe_repo_dir = repos.to_owned();
+        prefs.recipe_search_dirs = vec![autopkg.to_owned(), local.to_owned()];
+
+        let listings = list_recipes(&map, &prefs, &RecipeFilter::default());
+        let names: Vec<_> = listings
+            .iter()
+            .map(|listing| (listing.shortname.as_str(), listing.repo.as_deref()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("Firefox.download", Some("com.github.autopkg.recipes")),
+                ("Firefox.munki", Some("com.github.autopkg.recipes")),
+                ("thing.pkg", Some("Local"))
+            ]
+        );
+        assert_eq!(listings[2].path, local.join("thing.pkg.recipe.yaml"));
+
+        let filter = RecipeFilter {
+            repos: vec!["recipes".to_string()],
+            types: vec![".munki".to_string(), "pkg".to_string()],
+        };
+        let listings = list_recipes(&map, &prefs, &filter);
+        assert_eq!(listings.len(), 1);
+        assert_eq!(listings[0].identifier, "com.github.autopkg.munki.Firefox");
+    }
+
+    /// Preferences that search two fork repos, upstream first, and keep
+    /// overrides and the recipe map in the same temporary folder
+    fn create_collision_test_prefs(dir: &Path) -> Preferences {
+        for repo in ["upstream", "fork"] {
+            let repo_dir = dir.join(repo);
+            fs::create_dir_all(&repo_dir).unwrap();
+            write_test_recipe(&repo_dir, "test.download", None, "A", "EndOfCheckPhase");
+        }
+        let mut prefs = Preferences::new();
+        prefs.recipe_search_dirs = vec![dir.join("upstream"), dir.join("fork")];
+        prefs.recipe_repo_dir = dir.join("upstream");
+        prefs.recipe_override_dir = dir.join("overrides");
+        prefs.recipe_map_path = dir.join("recipe_map.json");
+        prefs
+    }
+
+    #[test]
+    fn test_build_recipe_map_records_collisions() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefs = create_collision_test_prefs(dir.path());
+        let upstream = dir.path().join("upstream/test.download.recipe.yaml");
+        let fork = dir.path().join("fork/test.download.recipe.yaml");
+        let (map, collisions) = build_recipe_map(&prefs, false).unwrap();
+        // The first search dir wins, and searching the upstream repo again
+        // as the recipe repo dir isn't a collision
+        assert_eq!(
+            find_recipe_in_map(&map, "test.download"),
+            Some(upstream.display().to_string())
+        );
+        assert_eq!(
+            collisions,
+            vec![
+                RecipeCollision {
+                    section: "identifiers",
+                    name: "test.download".to_string(),
+                    kept: upstream.display().to_string(),
+                    shadowed: fork.display().to_string(),
+                },
+                RecipeCollision {
+                    section: "shortnames",
+                    name: "test.download".to_string(),
+                    kept: upstream.display().to_string(),
+                    shadowed: fork.display().to_string(),
+                },
+            ]
+        );
+        assert_eq!(read_recipe_map(&prefs).unwrap(), map);
+        assert_eq!(
+            RecipeMapFile::read(&prefs.recipe_map_path).unwrap().map,
+            *map
+        );
+
+        // Swapping the search dirs swaps which recipe wins
+        let mut prefs = prefs;
+        prefs.recipe_search_dirs.reverse();
+        let (map, _) = build_recipe_map(&prefs, false).unwrap();
+        assert_eq!(
+            find_recipe_in_map(&map, "test.download"),
+            Some(fork.display().to_string())
+        );
+    }
+
+    #[test]
+    fn test_build_recipe_map_only_reads_changed_recipes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut prefs = create_collision_test_prefs(dir.path());
+        prefs.recipe_search_dirs.pop();
+        build_recipe_map(&prefs, false).unwrap();
+
+        // An unchanged recipe isn't read again, so a cached identifier is kept
+        let mut recipe_map_file = RecipeMapFile::read(&prefs.recipe_map_path).unwrap();
+        for info in recipe_map_file.recipe_files.values_mut() {
+            info.identifier = "test.cached".to_string();
+        }
+        fs::write(
+            &prefs.recipe_map_path,
+            serde_json::to_string(&recipe_map_file).unwrap(),
+        )
+        .unwrap();
+        let (map, _) = build_recipe_map(&prefs, false).unwrap();
+        assert!(map["identifiers"].contains_key("test.cached"));
+
+        // A recipe that changed size is read again
+        let recipe_path = write_test_recipe(
+            &dir.path().join("upstream"),
+            "test.download",
+            None,
+            "Changed",
+            "EndOfCheckPhase",
+        );
+        let (map, _) = build_recipe_map(&prefs, false).unwrap();
+        assert_eq!(map["identifiers"].get("test.download"), Some(&recipe_path));
+    }
+
+    #[test]
+    fn test_read_recipe_map_rebuilds_when_search_dirs_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut prefs = create_collision_test_prefs(dir.path());
+        prefs.recipe_search_dirs.pop();
+        // There's no map yet, so it's built on the first read
+        let map = read_recipe_map(&prefs).unwrap();
+        assert!(map["identifiers"].contains_key("test.download"));
+        // Forgetting the cached map acts like starting a new process
+        let forget_map = |prefs: &Preferences| {
+            RECIPE_MAP_CACHE
+                .lock()
+                .unwrap()
+                .remove(&prefs.recipe_map_path);
+        };
+
+        // A new recipe in a search dir is found by the next process
+        write_test_recipe(
+            &dir.path().join("upstream"),
+            "test.munki",
+            Some("test.download"),
+            "B",
+            "EndOfCheckPhase",
+        );
+        assert!(!read_recipe_map(&prefs).unwrap()["identifiers"].contains_key("test.munki"));
+        forget_map(&prefs);
+        assert!(read_recipe_map(&prefs).unwrap()["identifiers"].contains_key("test.munki"));
+
+        // A different set of search dirs doesn't get the cached map, even in
+        // the same process
+        prefs.recipe_search_dirs = vec![dir.path().join("fork")];
+        prefs.recipe_repo_dir = dir.path().join("fork");
+        let map = read_recipe_map(&prefs).unwrap();
+        assert!(!map["identifiers"].contains_key("test.munki"));
+    }
+
+    #[test]
+    fn test_read_recipe_map_rebuilds_when_autopkgignore_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut prefs = create_collision_test_prefs(dir.path());
+        prefs.recipe_search_dirs.pop();
+        let upstream = dir.path().join("upstream");
+        fs::create_dir_all(upstream.join("Archive")).unwrap();
+        write_test_recipe(
+            &upstream.join("Archive"),
+            "test.archived",
+            None,
+            "A",
+            "EndOfCheckPhase",
+        );
+        let ignore_path = upstream.join(AUTOPKGIGNORE_FILENAME);
+        fs::write(&ignore_path, "/Archive\n").unwrap();
+        let has_archived = |prefs: &Preferences| {
+            RECIPE_MAP_CACHE
+                .lock()
+                .unwrap()
+                .remove(&prefs.recipe_map_path);
+            read_recipe_map(prefs).unwrap()["identifiers"].contains_key("test.archived")
+        };
+        assert!(!has_archived(&prefs));
+
+        // Editing the file in place doesn't change any folder, only the file.
+        // Its time is set explicitly, so the test doesn't depend on how
+        // precise the filesystem's times are
+        let before = fs::metadata(&upstream).unwrap().modified().unwrap();
+        fs::write(&ignore_path, "# Nothing ignored\n").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&ignore_path)
+            .unwrap()
+            .set_modified(SystemTime::now() + std::time::Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(fs::metadata(&upstream).unwrap().modified().unwrap(), before);
+        assert!(has_archived(&prefs));
+    }
+
+    #[test]
+    fn test_read_recipe_map_rebuilds_when_an_identifier_is_edited() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut prefs = create_collision_test_prefs(dir.path());
+        prefs.recipe_search_dirs.pop();
+        let upstream = dir.path().join("upstream");
+        let identifiers = |prefs: &Preferences| {
+            RECIPE_MAP_CACHE
+                .lock()
+                .unwrap()
+                .remove(&prefs.recipe_map_path);
+            read_recipe_map(prefs).unwrap()["identifiers"].clone()
+        };
+        assert!(identifiers(&prefs).contains_key("test.download"));
+
+        // Editing the Identifier in place doesn't change any folder, only the
+        // recipe file. Its time is set explicitly, so the test doesn't depend
+        // on how precise the filesystem's times are
+        let before = fs::metadata(&upstream).unwrap().modified().unwrap();
+        let recipe_path = upstream.join("test.download.recipe.yaml");
+        let recipe_yaml = fs::read_to_string(&recipe_path).unwrap();
+        fs::write(
+            &recipe_path,
+            recipe_yaml.replace("Identifier: test.download", "Identifier: test.renamed"),
+        )
+        .unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&recipe_path)
+            .unwrap()
+            .set_modified(SystemTime::now() + std::time::Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(fs::metadata(&upstream).unwrap().modified().unwrap(), before);
+        let identifiers = identifiers(&prefs);
+        assert!(identifiers.contains_key("test.renamed"));
+        assert!(!identifiers.contains_key("test.download"));
+    }
+
+    #[test]
+    fn test_build_recipe_map_in_parallel_matches_serial() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut prefs = create_collision_test_prefs(dir.path());
+        for repo in ["upstream", "fork", "other"] {
+            let repo_dir = dir.path().join(repo).join("Apps");
+            fs::create_dir_all(&repo_dir).unwrap();
+            for app in 0..20 {
+                let identifier = format!("com.github.{repo}.download.App{app}");
+                write_test_recipe(&repo_dir, &identifier, None, "A", "EndOfCheckPhase");
+            }
+        }
+        prefs.recipe_search_dirs.push(dir.path().join("other"));
+
+        let serial_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let (serial_map, serial_collisions) = serial_pool
+            .install(|| build_recipe_map(&prefs, false))
+            .unwrap();
+        let serial_file = fs::read(&prefs.recipe_map_path).unwrap();
+        // Start from scratch, so every recipe is read again
+        fs::remove_file(&prefs.recipe_map_path).unwrap();
+        let (parallel_map, parallel_collisions) = build_recipe_map(&prefs, false).unwrap();
+        assert_eq!(parallel_map, serial_map);
+        assert_eq!(parallel_collisions, serial_collisions);
+        assert_eq!(fs::read(&prefs.recipe_map_path).unwrap(), serial_file);
+    }
+
+    #[test]
+    fn test_search_dir_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let deep_dir = dir.path().join("Apps/Firefox/Tests/Fixtures");
+        fs::create_dir_all(&deep_dir).unwrap();
+        fs::create_dir_all(dir.path().join("Archive")).unwrap();
+        write_test_recipe(dir.path(), "test.top", None, "A", "EndOfCheckPhase");
+        write_test_recipe(&deep_dir, "test.deep", None, "A", "EndOfCheckPhase");
+        write_test_recipe(
+            &dir.path().join("Archive"),
+            "test.archived",
+            None,
+            "A",
+            "EndOfCheckPhase",
+        );
+        let identifiers = |settings: &SearchDirSettings| -> Vec<String> {
+            get_recipe_names_from_folder(dir.path(), settings, &BTreeMap::new())
+                .recipe_names
+                .into_iter()
+                .map(|(identifier, _, _)| identifier)
+                .collect()
+        };
+
+        // By default, recipes past three folders deep aren't found
+        let mut settings = SearchDirSettings::default();
+        assert_eq!(identifiers(&settings), ["test.archived", "test.top"]);
+        settings.max_depth = 5;
+        assert_eq!(
+            identifiers(&settings),
+            ["test.deep", "test.archived", "test.top"]
+        );
+        settings.ignore = vec!["Tests".to_string()];
+        fs::write(dir.path().join(".autopkgignore"), "/Archive\n").unwrap();
+        assert_eq!(identifiers(&settings), ["test.top"]);
+        settings.use_autopkgignore = false;
+        assert_eq!(identifiers(&settings), ["test.archived", "test.top"]);
+    }
+
+    #[test]
+    fn test_build_recipe_map_strict() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefs = create_collision_test_prefs(dir.path());
+        let err = build_recipe_map(&prefs, true).unwrap_err();
+        assert!(matches!(&err, Error::RecipeCollisions(collisions) if collisions.len() == 2));
+        assert!(!prefs.recipe_map_path.exists());
+
+        fs::remove_dir_all(dir.path().join("fork")).unwrap();
+        let (_, collisions) = build_recipe_map(&prefs, true).unwrap();
+        assert!(collisions.is_empty());
+    }
+
+    #[test]
+    fn test_find_recipe_in_map_prefers_overrides() {
+        let section = |entries: &[(&str, &str)]| -> BTreeMap<String, String> {
+            entries
+                .iter()
+                .map(|(name, path)| (name.to_string(), path.to_string()))
+                .collect()
+        };
+        let map: RecipeMap = BTreeMap::from([
+            (
+                "identifiers".to_string(),
+                section(&[
+                    (
+                        "com.github.test.download.Firefox",
+                        "recipes/Firefox.download.recipe",
+                    ),
+                    ("Firefox.download", "recipes/Confusing.download.recipe"),
+                ]),
+            ),
+            (
+                "overrides".to_string(),
+                section(&[
+                    ("Firefox.download", "overrides/Firefox.download.recipe"),
+                    (
+                        "local.download.Firefox",
+                        "overrides/Firefox.download.recipe",
+                    ),
+                ]),
+            ),
+            (
+                "shortnames".to_string(),
+                section(&[
+                    ("Firefox.download", "recipes/Firefox.download.recipe"),
+                    ("Chrome.download", "recipes/Chrome.download.recipe"),
+                ]),
+            ),
+        ]);
+        // Overrides beat everything else
+        assert_eq!(
+            find_recipe_in_map(&map, "Firefox.download").as_deref(),
+            Some("overrides/Firefox.download.recipe")
+        );
+        assert_eq!(
+            find_recipe_in_map(&map, "local.download.Firefox").as_deref(),
+            Some("overrides/Firefox.download.recipe")
+        );
+        // Identifiers are found without an override
+        assert_eq!(
+            find_recipe_in_map(&map, "com.github.test.download.Firefox").as_deref(),
+            Some("recipes/Firefox.download.recipe")
+        );
+        // And shortnames are the last resort
+        assert_eq!(
+            find_recipe_in_map(&map, "Chrome.download").as_deref(),
+            Some("recipes/Chrome.download.recipe")
+        );
+        assert_eq!(find_recipe_in_map(&map, "Safari.download"), None);
+    }
+
+    #[test]
+    fn test_version_equal_or_greater() {
+        assert!(version_equal_or_greater("2.3", "2.3.0"));
+        assert!(version_equal_or_greater("2.10", "2.9"));
+        assert!(!version_equal_or_greater("1.4.1", "2.3"));
     }
 
     #[test]
     fn test_calculate_short_name() {
         assert_eq!(
-            "MyRecipe.download",
+            Some("MyRecipe.download".to_string()),
             calculate_short_name(Path::new("/Path/test/MyRecipe.download.recipe"))
-        )
+        );
+        assert_eq!(
+            Some("Firefox.download".to_string()),
+            calculate_short_name(Path::new("/Path/test/Firefox.download.recipe.yaml"))
+        );
+        assert_eq!(
+            Some("Firefox.munki".to_string()),
+            calculate_short_name(Path::new("Firefox.munki.recipe.plist"))
+        );
+    }
+
+    #[test]
+    fn test_recipe_format() {
+        assert_eq!(
+            recipe_format(Path::new("Firefox.download.recipe")),
+            Some(RecipeFormat::Plist)
+        );
+        assert_eq!(
+            recipe_format(Path::new("Firefox.download.recipe.plist")),
+            Some(RecipeFormat::Plist)
+        );
+        assert_eq!(
+            recipe_format(Path::new("Firefox.download.recipe.yaml")),
+            Some(RecipeFormat::Yaml)
+        );
+        assert_eq!(recipe_format(Path::new("Firefox.download.yaml")), None);
+        assert_eq!(recipe_format(Path::new("Firefox.recipe.json")), None);
+    }
+
+    #[test]
+    fn test_get_recipe_names_from_folder_finds_every_extension() {
+        let sample_data = Path::new(env!("CARGO_MANIFEST_DIR")).join("sample_data");
+        let scan = get_recipe_names_from_folder(
+            &sample_data,
+            &SearchDirSettings::default(),
+            &BTreeMap::new(),
+        );
+        let mut shortnames: Vec<&str> = scan
+            .recipe_names
+            .iter()
+            .map(|(_, shortname, _)| shortname.as_str())
+            .collect();
+        shortnames.sort();
+        assert_eq!(
+            shortnames,
+            vec![
+                "CitrixWorkspace.download",
+                "CitrixWorkspace.download",
+                "GoogleChromePkg.download",
+                "GoogleChrome_override.download",
+                "Tuple.download",
+                "Tuple.munki",
+            ]
+        );
     }
 }
@@ -0,0 +1,269 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use tracing::{debug, warn};
+
+use crate::error::{Error, Result};
+use crate::processors::ProcessorRegistry;
+use crate::recipes::trust::get_trust_info;
+use crate::recipes::{
+    calculate_short_name, load_recipe_ignoring_overrides, read_recipe_map, write_recipe_data,
+    ParentRecipeTrust, PlistDataType, Recipe, RecipeFormat, RecipeMap,
+};
+use crate::Preferences;
+
+/// A recipe override, which only changes the Input of its parent.
+///
+/// The fields are in alphabetical order, the same order Python AutoPkg
+/// writes them in, so overrides written by either are interchangeable.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RecipeOverride {
+    pub identifier: String,
+    pub input: BTreeMap<String, PlistDataType>,
+    pub parent_recipe: String,
+    pub parent_recipe_trust_info: ParentRecipeTrust,
+}
+
+/// Options for make-override
+#[derive(Debug)]
+pub struct OverrideOptions {
+    /// Name for the override file, instead of the recipe's shortname
+    pub name: Option<String>,
+    /// Replace an existing override with the same name
+    pub force: bool,
+    /// Make an override even if the recipe or one of its parents is
+    /// deprecated
+    pub ignore_deprecation: bool,
+    pub format: RecipeFormat,
+}
+
+/// Build an override identifier from an override name, by reversing its
+/// parts and prefixing it with "local.", so Firefox.munki becomes
+/// local.munki.Firefox
+fn override_identifier(override_name: &str) -> String {
+    let reversed_name: Vec<&str> = override_name.split('.').rev().collect();
+    format!("local.{}", reversed_name.join("."))
+}
+
+/// Generate an override for a loaded recipe, trusting its current parents
+/// and non-core processors
+pub fn generate_recipe_override(
+    recipe: &Recipe,
+    override_name: &str,
+    recipe_map: &RecipeMap,
+    registry: &ProcessorRegistry,
+) -> Result<RecipeOverride> {
+    debug!(
+        "Generating override {override_name} for {}",
+        recipe.identifier
+    );
+    let mut input: BTreeMap<String, PlistDataType> = recipe.input.clone().into_iter().collect();
+    // The identifier is the override's own, not its parent's
+    input.remove("IDENTIFIER");
+    Ok(RecipeOverride {
+        identifier: override_identifier(override_name),
+        input,
+        parent_recipe: recipe.identifier.to_owned(),
+        parent_recipe_trust_info: get_trust_info(recipe, recipe_map, registry)?,
+    })
+}
+
+/// Make an override for a recipe and write it to the override folder,
+/// returning the path of the new override.
+///
+/// The recipe and its parents are always loaded from the search dirs, even
+/// if an override for it already exists.
+pub fn make_override(
+    name: &str,
+    prefs: &Preferences,
+    registry: &ProcessorRegistry,
+    options: &OverrideOptions,
+) -> Result<PathBuf> {
+    let recipe = load_recipe_ignoring_overrides(name, prefs)?;
+    let recipe_path = recipe.recipe_path.to_owned().unwrap_or_default();
+    let deprecated = recipe
+        .process
+        .iter()
+        .any(|step| step.processor == "DeprecationWarning");
+    if deprecated && !options.ignore_deprecation {
+        return Err(Error::InvalidRecipe {
+            recipe: name.to_string(),
+            message: format!(
+                "{} or one of its parents is deprecated. Will not make an override. \
+                Use --ignore-deprecation to make an override regardless of deprecation status.",
+                recipe_path.display()
+            ),
+        });
+    } else if deprecated {
+        warn!(
+            "{} or one of its parents is deprecated. Making an override anyway, \
+            because --ignore-deprecation is specified.",
+            recipe_path.display()
+        );
+    }
+    if recipe.identifier.is_empty() {
+        return Err(Error::InvalidRecipe {
+            recipe: name.to_string(),
+            message: "missing an Identifier. Cannot make an override.".to_string(),
+        });
+    }
+
+    let override_name = match &options.name {
+        Some(override_name) => override_name.to_owned(),
+        None => calculate_short_name(&recipe_path).unwrap_or(name.to_string()),
+    };
+    let override_dir = &prefs.recipe_override_dir;
+    fs::create_dir_all(override_dir).map_err(|e| Error::io(override_dir, e))?;
+    let override_path = match options.format {
+        RecipeFormat::Plist => override_dir.join(format!("{override_name}.recipe")),
+        RecipeFormat::Yaml => override_dir.join(format!("{override_name}.recipe.yaml")),
+    };
+    if override_path.exists() && !options.force {
+        return Err(Error::OverrideExists(override_path));
+    }
+
+    let recipe_map = read_recipe_map(prefs)?;
+    let recipe_override = generate_recipe_override(&recipe, &override_name, &recipe_map, registry)?;
+    write_recipe_data(&override_path, &recipe_override, options.format)?;
+    Ok(override_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+    use std::path::Path;
+
+    use super::*;
+    use crate::recipes::{read_recipe, write_test_recipe_map};
+
+    /// Write a download recipe, and an override of it that make-override
+    /// should ignore, and return preferences with a recipe map that finds
+    /// both.
+    ///
+    /// This is only used for tests
+    fn create_test_prefs(dir: &Path) -> Preferences {
+        let recipes_dir = dir.join("recipes");
+        fs::create_dir_all(&recipes_dir).unwrap();
+        let recipe_path = recipes_dir.join("Test.download.recipe.yaml");
+        fs::write(
+            &recipe_path,
+            "Identifier: com.github.autopkg.download.Test\n\
+            Input:\n  NAME: Test\n  IDENTIFIER: com.github.autopkg.download.Test\n\
+            Process:\n  - Processor: EndOfCheckPhase\n",
+        )
+        .unwrap();
+        let existing_override_path = dir.join("Existing.download.recipe.yaml");
+        fs::write(
+            &existing_override_path,
+            "Identifier: local.download.Test\n\
+            ParentRecipe: com.github.autopkg.download.Test\n",
+        )
+        .unwrap();
+
+        let mut prefs = Preferences::new();
+        prefs.recipe_override_dir = dir.join("overrides");
+        prefs.recipe_map_path = dir.join("recipe_map.json");
+        write_test_recipe_map(
+            &prefs,
+            &[
+                (
+                    "identifiers",
+                    "com.github.autopkg.download.Test",
+                    &recipe_path,
+                ),
+                ("overrides", "Test.download", &existing_override_path),
+                ("shortnames", "Test.download", &recipe_path),
+            ],
+        );
+        prefs
+    }
+
+    fn create_test_options(format: RecipeFormat) -> OverrideOptions {
+        OverrideOptions {
+            name: None,
+            force: false,
+            ignore_deprecation: false,
+            format,
+        }
+    }
+
+    #[test]
+    fn test_override_identifier() {
+        assert_eq!(override_identifier("Firefox.munki"), "local.munki.Firefox");
+        assert_eq!(override_identifier("Firefox"), "local.Firefox");
+    }
+
+    #[test]
+    fn test_make_override_in_each_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefs = create_test_prefs(dir.path());
+        let registry = ProcessorRegistry::new();
+        for (format, file_name) in [
+            (RecipeFormat::Plist, "Test.download.recipe"),
+            (RecipeFormat::Yaml, "Test.download.recipe.yaml"),
+        ] {
+            let override_path = make_override(
+                "Test.download",
+                &prefs,
+                &registry,
+                &create_test_options(format),
+            )
+            .unwrap();
+            assert_eq!(override_path, prefs.recipe_override_dir.join(file_name));
+            let recipe_override = read_recipe(&override_path).unwrap();
+            assert_eq!(recipe_override.identifier, "local.download.Test");
+            assert_eq!(
+                recipe_override.parent_recipe.as_deref(),
+                Some("com.github.autopkg.download.Test")
+            );
+            assert_eq!(
+                recipe_override.input,
+                IndexMap::from([("NAME".to_string(), PlistDataType::Str("Test".to_string()))])
+            );
+            let trust_info = recipe_override.parent_recipe_trust_info.unwrap();
+            assert_eq!(
+                trust_info.parent_recipes.keys().collect::<Vec<_>>(),
+                vec!["com.github.autopkg.download.Test"]
+            );
+        }
+    }
+
+    #[test]
+    fn test_make_override_name_and_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefs = create_test_prefs(dir.path());
+        let registry = ProcessorRegistry::new();
+        let mut options = create_test_options(RecipeFormat::Yaml);
+        options.name = Some("MyTest.download".to_string());
+        let override_path = make_override("Test.download", &prefs, &registry, &options).unwrap();
+        assert_eq!(
+            read_recipe(&override_path).unwrap().identifier,
+            "local.download.MyTest"
+        );
+
+        let err = make_override("Test.download", &prefs, &registry, &options).unwrap_err();
+        assert!(matches!(err, Error::OverrideExists(_)));
+        options.force = true;
+        make_override("Test.download", &prefs, &registry, &options).unwrap();
+    }
+
+    #[test]
+    fn test_make_override_refuses_deprecated_recipes() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefs = create_test_prefs(dir.path());
+        fs::write(
+            dir.path().join("recipes/Test.download.recipe.yaml"),
+            "Identifier: com.github.autopkg.download.Test\n\
+            Process:\n  - Processor: DeprecationWarning\n",
+        )
+        .unwrap();
+        let registry = ProcessorRegistry::new();
+        let mut options = create_test_options(RecipeFormat::Plist);
+        let err = make_override("Test.download", &prefs, &registry, &options).unwrap_err();
+        assert!(matches!(err, Error::InvalidRecipe { .. }));
+        options.ignore_deprecation = true;
+        make_override("Test.download", &prefs, &registry, &options).unwrap();
+    }
+}
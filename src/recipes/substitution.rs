@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use tracing::{trace, warn};
+
+use crate::recipes::PlistDataType;
+
+/// Return the name of the `%NAME%` token at the start of `text`, if there is
+/// one.
+///
+/// This matches the same names as Python AutoPkg's RE_KEYREF:
+/// `%[a-zA-Z_][a-zA-Z_0-9]*%`
+fn token_at_start(text: &str) -> Option<&str> {
+    let name = text.strip_prefix('%')?;
+    let end = name.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))?;
+    let starts_with_letter = name
+        .chars()
+        .next()
+        .map(|c| c.is_ascii_alphabetic() || c == '_')
+        .unwrap_or(false);
+    if starts_with_letter && name[end..].starts_with('%') {
+        Some(&name[..end])
+    } else {
+        None
+    }
+}
+
+/// Expand every `%NAME%` token in a string using values from the environment.
+///
+/// - If any token is undefined, the whole string is left unchanged, like
+///   Python AutoPkg, so `%pathname%/%NAME%` stays as-is until a processor has
+///   set `pathname`
+/// - Numbers, booleans and dates are formatted like Python's `str()`, so
+///   `%MAX_RETRIES%` becomes `3` and a true value becomes `True`. Arrays,
+///   dictionaries and data are left untouched
+/// - There's no escape for a literal `%`, so `%%` is left as it is
+///
+/// Tokens are matched left to right without overlapping, like Python's
+/// `re.sub`, so `%NAME%VERSION%` only substitutes NAME.
+pub fn substitute_str(text: &str, env: &HashMap<String, PlistDataType>) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(index) = rest.find('%') {
+        expanded.push_str(&rest[..index]);
+        rest = &rest[index..];
+        if let Some(name) = token_at_start(rest) {
+            // The whole token is the name plus both percent signs
+            let token_length = name.len() + 2;
+            match env.get(name) {
+                Some(value) => match value.to_python_string() {
+                    Some(value) => {
+                        trace!("Substituting %{name}% with {value}");
+                        expanded.push_str(&value);
+                    }
+                    None => {
+                        warn!("Can't substitute an array, dictionary or data value of %{name}%");
+                        expanded.push_str(&rest[..token_length]);
+                    }
+                },
+                None => {
+                    warn!("Use of undefined key in variable substitution: %{name}%");
+                    return text.to_string();
+                }
+            }
+            rest = &rest[token_length..];
+        } else {
+            expanded.push('%');
+            rest = &rest[1..];
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Recursively expand `%NAME%` tokens in a value, including every string
+/// inside arrays and dictionaries.
+///
+/// Dictionary keys are never substituted, only their values.
+pub fn substitute(value: &PlistDataType, env: &HashMap<String, PlistDataType>) -> PlistDataType {
+    match value {
+        PlistDataType::Str(text) => PlistDataType::Str(substitute_str(text, env)),
+        PlistDataType::Array(array) => {
+            PlistDataType::Array(array.iter().map(|value| substitute(value, env)).collect())
+        }
+        PlistDataType::Dict(dict) => PlistDataType::Dict(
+            dict.iter()
+                .map(|(key, value)| (key.to_owned(), substitute(value, env)))
+                .collect(),
+        ),
+        _ => value.clone(),
+    }
+}
+
+/// Set a key in the environment to a value, after expanding any `%NAME%`
+/// tokens in the value against the current environment.
+///
+/// This is the equivalent of Python AutoPkg's `update_data()`.
+pub fn update_data(env: &mut HashMap<String, PlistDataType>, key: &str, value: &PlistDataType) {
+    let value = substitute(value, env);
+    env.insert(key.to_string(), value);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    /// Create an environment with a few common recipe variables
+    fn create_test_env() -> HashMap<String, PlistDataType> {
+        HashMap::from([
+            (
+                "NAME".to_string(),
+                PlistDataType::Str("Firefox".to_string()),
+            ),
+            (
+                "VERSION".to_string(),
+                PlistDataType::Str("128.0".to_string()),
+            ),
+            (
+                "_private".to_string(),
+                PlistDataType::Str("underscore".to_string()),
+            ),
+            ("verbose".to_string(), PlistDataType::Bool(true)),
+            ("MAX_RETRIES".to_string(), PlistDataType::Integer(3.into())),
+            ("THRESHOLD".to_string(), PlistDataType::Real(0.5)),
+            (
+                "ARCHS".to_string(),
+                PlistDataType::from(vec!["arm64".to_string()]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_substitute_str_simple() {
+        let env = create_test_env();
+        assert_eq!(substitute_str("%NAME%.dmg", &env), "Firefox.dmg");
+        assert_eq!(substitute_str("%NAME%-%VERSION%", &env), "Firefox-128.0");
+        assert_eq!(substitute_str("%NAME%%VERSION%", &env), "Firefox128.0");
+        assert_eq!(substitute_str("%_private%", &env), "underscore");
+        assert_eq!(substitute_str("no tokens here", &env), "no tokens here");
+    }
+
+    #[test]
+    fn test_substitute_str_unknown_token_leaves_string_unchanged() {
+        let env = create_test_env();
+        assert_eq!(substitute_str("%pathname%", &env), "%pathname%");
+        assert_eq!(
+            substitute_str("%NAME%/%pathname%/%VERSION%", &env),
+            "%NAME%/%pathname%/%VERSION%"
+        );
+    }
+
+    #[test]
+    fn test_substitute_str_formats_scalars_like_python() {
+        let env = create_test_env();
+        assert_eq!(
+            substitute_str("--retries=%MAX_RETRIES%", &env),
+            "--retries=3"
+        );
+        assert_eq!(substitute_str("%THRESHOLD%", &env), "0.5");
+        assert_eq!(substitute_str("verbose=%verbose%", &env), "verbose=True");
+        // Arrays have no text form, so their token is kept
+        assert_eq!(substitute_str("%ARCHS%-%NAME%", &env), "%ARCHS%-Firefox");
+    }
+
+    #[test]
+    fn test_substitute_str_quirks() {
+        let env = create_test_env();
+        // Tokens don't overlap, so the trailing VERSION% is plain text
+        assert_eq!(substitute_str("%NAME%VERSION%", &env), "FirefoxVERSION%");
+        // Names can't start with a digit, which keeps URL-encoding intact
+        assert_eq!(
+            substitute_str("accept_tos%3Dhttps%253A%252F%NAME%", &env),
+            "accept_tos%3Dhttps%253A%252FFirefox"
+        );
+        // Spaces and punctuation aren't part of names
+        assert_eq!(substitute_str("50% off %NAME%", &env), "50% off Firefox");
+        assert_eq!(substitute_str("%NA-ME%", &env), "%NA-ME%");
+        // A lone or trailing percent sign is left alone
+        assert_eq!(substitute_str("%", &env), "%");
+        assert_eq!(substitute_str("%NAME", &env), "%NAME");
+    }
+
+    #[test]
+    fn test_substitute_str_double_percent_is_not_an_escape() {
+        let env = create_test_env();
+        assert_eq!(substitute_str("100%%", &env), "100%%");
+        assert_eq!(substitute_str("%%NAME%%", &env), "%Firefox%");
+        assert_eq!(substitute_str("%NAME%%%", &env), "Firefox%%");
+    }
+
+    #[test]
+    fn test_substitute_nested() {
+        let env = create_test_env();
+        let value = PlistDataType::Dict(BTreeMap::from([
+            (
+                "paths".to_string(),
+                PlistDataType::from(vec!["%NAME%.app".to_string(), "%pathname%".to_string()]),
+            ),
+            (
+                "pkginfo".to_string(),
+                PlistDataType::Dict(BTreeMap::from([(
+                    "%NAME%".to_string(),
+                    PlistDataType::from("%NAME% %VERSION%"),
+                )])),
+            ),
+            ("unattended".to_string(), PlistDataType::Bool(true)),
+            ("retries".to_string(), PlistDataType::Integer(3.into())),
+        ]));
+        let expected = PlistDataType::Dict(BTreeMap::from([
+            (
+                "paths".to_string(),
+                PlistDataType::from(vec!["Firefox.app".to_string(), "%pathname%".to_string()]),
+            ),
+            (
+                "pkginfo".to_string(),
+                // Keys are never substituted
+                PlistDataType::Dict(BTreeMap::from([(
+                    "%NAME%".to_string(),
+                    PlistDataType::from("Firefox 128.0"),
+                )])),
+            ),
+            ("unattended".to_string(), PlistDataType::Bool(true)),
+            ("retries".to_string(), PlistDataType::Integer(3.into())),
+        ]));
+        assert_eq!(substitute(&value, &env), expected);
+    }
+
+    #[test]
+    fn test_update_data() {
+        let mut env = create_test_env();
+        update_data(
+            &mut env,
+            "filename",
+            &PlistDataType::Str("%NAME%-%VERSION%.dmg".to_string()),
+        );
+        assert_eq!(
+            env["filename"],
+            PlistDataType::Str("Firefox-128.0.dmg".to_string())
+        );
+        // A key can refer to its own previous value
+        update_data(
+            &mut env,
+            "NAME",
+            &PlistDataType::Str("%NAME% ESR".to_string()),
+        );
+        assert_eq!(env["NAME"], PlistDataType::Str("Firefox ESR".to_string()));
+    }
+}
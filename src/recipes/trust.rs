@@ -0,0 +1,469 @@
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use crate::error::{Error, Result};
+use crate::processors::{extract_processor_name_with_recipe_identifier, ProcessorRegistry};
+use crate::recipes::{
+    calculate_short_name, find_recipe_in_map, get_parent_identifier, load_recipe, read_recipe,
+    read_recipe_map, recipe_format, write_recipe_data, ParentRecipeTrust, Recipe, RecipeFormat,
+    RecipeMap, TrustBlock,
+};
+use crate::Preferences;
+
+/// Stored in place of a hash when a path isn't a file, the same as Python
+/// AutoPkg
+const NOT_A_FILE: &str = "NOT A FILE";
+/// Stored in place of a hash when a non-core processor's source can't be found
+const PROCESSOR_NOT_FOUND: &str = "PROCESSOR FILEPATH NOT FOUND";
+
+/// Format a digest as a lowercase hex string
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Generate a SHA-256 hash of the file at path
+pub fn sha256_hash(path: &Path) -> Result<String> {
+    if !path.is_file() {
+        return Ok(NOT_A_FILE.to_string());
+    }
+    let mut file = fs::File::open(path).map_err(|e| Error::io(path, e))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).map_err(|e| Error::io(path, e))?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Whether a path is inside a git working tree
+fn is_in_git_work_tree(path: &Path) -> bool {
+    // .git is a folder in a normal clone, but a file in worktrees and
+    // submodules, so we only check that it exists
+    path.canonicalize()
+        .map(|path| path.ancestors().any(|dir| dir.join(".git").exists()))
+        .unwrap_or(false)
+}
+
+/// Generate the git blob hash of the file at path, the same value as
+/// `git hash-object <path>`, without needing git installed.
+///
+/// Returns None if the file isn't inside a git working tree.
+pub fn git_blob_hash(path: &Path) -> Result<Option<String>> {
+    if !path.is_file() || !is_in_git_work_tree(path) {
+        return Ok(None);
+    }
+    let contents = fs::read(path).map_err(|e| Error::io(path, e))?;
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", contents.len()));
+    hasher.update(&contents);
+    Ok(Some(to_hex(&hasher.finalize())))
+}
+
+/// Replace the home folder at the start of a path with ~, the inverse of
+/// expanding it
+pub fn compress_user(path: &Path) -> String {
+    match dirs::home_dir().and_then(|home| path.strip_prefix(home).ok()) {
+        Some(relative) if relative.as_os_str().is_empty() => "~".to_string(),
+        Some(relative) => format!("~/{}", relative.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// Hash a file into a TrustBlock
+fn trust_block(path: &Path) -> Result<TrustBlock> {
+    Ok(TrustBlock {
+        git_hash: git_blob_hash(path)?,
+        path: compress_user(path),
+        sha256_hash: sha256_hash(path)?,
+    })
+}
+
+/// Find the source file of a non-core processor used by a recipe.
+///
+/// Like Python AutoPkg, this looks for `<ProcessorName>.py` next to the
+/// recipe, next to the recipe that shares the processor (if the name
+/// includes a recipe identifier), and next to each parent recipe.
+pub fn find_processor_path(
+    processor_name: &str,
+    recipe: &Recipe,
+    recipe_map: &RecipeMap,
+) -> Option<PathBuf> {
+    let (processor_name, recipe_identifier) =
+        extract_processor_name_with_recipe_identifier(processor_name);
+    let shared_recipe_path = recipe_identifier
+        .and_then(|identifier| recipe_map.get("identifiers")?.get(identifier))
+        .map(PathBuf::from);
+    recipe
+        .recipe_path
+        .iter()
+        .chain(shared_recipe_path.iter())
+        .chain(recipe.parent_recipes.iter())
+        .filter_map(|recipe_path| recipe_path.parent())
+        .map(|dir| dir.join(format!("{processor_name}.py")))
+        .find(|processor_path| processor_path.exists())
+}
+
+/// Generate the trust info for a loaded recipe: hashes of the recipe file,
+/// every one of its parents, and every non-core processor it uses.
+pub fn get_trust_info(
+    recipe: &Recipe,
+    recipe_map: &RecipeMap,
+    registry: &ProcessorRegistry,
+) -> Result<ParentRecipeTrust> {
+    let mut trust_info = ParentRecipeTrust::default();
+    for recipe_path in recipe.parent_recipes.iter().chain(&recipe.recipe_path) {
+        let identifier = read_recipe(recipe_path)?.identifier;
+        trust_info
+            .parent_recipes
+            .insert(identifier, trust_block(recipe_path)?);
+    }
+    for step in &recipe.process {
+        if registry.is_core(&step.processor) {
+            continue;
+        }
+        let block = match find_processor_path(&step.processor, recipe, recipe_map) {
+            Some(processor_path) => trust_block(&processor_path)?,
+            None => {
+                warn!("Processor path not found for processor: {}", step.processor);
+                TrustBlock {
+                    git_hash: None,
+                    path: String::new(),
+                    sha256_hash: PROCESSOR_NOT_FOUND.to_string(),
+                }
+            }
+        };
+        trust_info
+            .non_core_processors
+            .insert(step.processor.to_owned(), block);
+    }
+    Ok(trust_info)
+}
+
+/// Describe every difference between the expected and actual hashes of one
+/// kind of trusted file, such as "Processor" or "Parent recipe"
+fn describe_trust_differences(
+    kind: &str,
+    expected: &BTreeMap<String, TrustBlock>,
+    actual: &BTreeMap<String, TrustBlock>,
+    verbosity: u8,
+) -> Vec<String> {
+    let mut differences = Vec::new();
+    for (name, expected_block) in expected {
+        match actual.get(name) {
+            Some(actual_block) if actual_block.sha256_hash == expected_block.sha256_hash => {}
+            Some(actual_block) if !actual_block.path.is_empty() => {
+                differences.push(format!("{kind} {name} contents differ from expected."));
+                differences.push(format!("    Path: {}", actual_block.path));
+                if verbosity > 1 {
+                    differences.push(format!(
+                        "    Expected SHA-256: {}",
+                        expected_block.sha256_hash
+                    ));
+                    differences.push(format!("    Actual SHA-256: {}", actual_block.sha256_hash));
+                }
+            }
+            _ => {
+                differences.push(format!(
+                    "Expected {} {name} can't be found.",
+                    kind.to_lowercase()
+                ));
+                if verbosity > 1 && !expected_block.path.is_empty() {
+                    differences.push(format!("    Expected path: {}", expected_block.path));
+                }
+            }
+        }
+    }
+    for (name, actual_block) in actual {
+        if !expected.contains_key(name) {
+            differences.push(format!("Unexpected {} found: {name}", kind.to_lowercase()));
+            if !actual_block.path.is_empty() {
+                differences.push(format!("    Path: {}", actual_block.path));
+            }
+        }
+    }
+    differences
+}
+
+/// Verify the trust info stored in a loaded recipe override against its
+/// parent recipes and non-core processors as they are on disk now.
+///
+/// Returns Error::TrustVerification describing what changed, or what's
+/// wrong with the trust info. A verbosity above 1 adds hints and hashes to
+/// the description.
+pub fn verify_parent_trust(
+    recipe: &Recipe,
+    prefs: &Preferences,
+    registry: &ProcessorRegistry,
+    verbosity: u8,
+) -> Result<()> {
+    let recipe_path = recipe.recipe_path.to_owned().unwrap_or_default();
+    let recipe_name = calculate_short_name(&recipe_path).unwrap_or(recipe.identifier.to_owned());
+    let in_override_dir = recipe_path.starts_with(&prefs.recipe_override_dir);
+    let fail = |message: String| {
+        Err(Error::TrustVerification {
+            recipe: recipe.identifier.to_owned(),
+            message,
+        })
+    };
+
+    let Some(expected_trust_info) = &recipe.parent_recipe_trust_info else {
+        let mut message = "No trust information present.".to_string();
+        if verbosity > 1 {
+            if in_override_dir && !recipe.parent_recipes.is_empty() {
+                message.push_str(&format!(
+                    "\nAudit the parent recipe, then run:\n\tautopkg update-trust-info {recipe_name}"
+                ));
+            } else {
+                message.push_str(&format!(
+                    "\nAudit the recipe, then store trust info by running:\n\tautopkg make-override {recipe_name}"
+                ));
+            }
+        }
+        return fail(message);
+    };
+    if !in_override_dir {
+        let mut message = "Trust information in non-override recipe.".to_string();
+        if verbosity > 1 {
+            message.push_str(&format!(
+                "\nTrust info should only be stored in local recipe overrides.\nTrust info found in {}",
+                recipe_path.display()
+            ));
+        }
+        return fail(message);
+    }
+    // Trust info in a cloned recipe repo was stored by someone else, so it
+    // can't be used
+    if recipe_path.starts_with(&prefs.recipe_repo_dir) {
+        return fail(
+            "Recipe from external repo: embedded trust info will be ignored. \
+            Audit the recipe, then create an override to trust it."
+                .to_string(),
+        );
+    }
+    let Some(parent_identifier) = get_parent_identifier(recipe) else {
+        return fail("Trust information present, but there is no ParentRecipe.".to_string());
+    };
+
+    let parent_recipe = load_recipe(&parent_identifier, prefs)?;
+    let recipe_map = read_recipe_map(prefs)?;
+    let actual_trust_info = get_trust_info(&parent_recipe, &recipe_map, registry)?;
+    if &actual_trust_info == expected_trust_info {
+        debug!("Trust info for {} matches", recipe.identifier);
+        return Ok(());
+    }
+    let mut differences = describe_trust_differences(
+        "Processor",
+        &expected_trust_info.non_core_processors,
+        &actual_trust_info.non_core_processors,
+        verbosity,
+    );
+    differences.extend(describe_trust_differences(
+        "Parent recipe",
+        &expected_trust_info.parent_recipes,
+        &actual_trust_info.parent_recipes,
+        verbosity,
+    ));
+    // Only the hashes matter, so paths or git hashes that changed on their own
+    // don't fail verification
+    if differences.is_empty() {
+        return Ok(());
+    }
+    fail(differences.join("\n"))
+}
+
+/// Replace the ParentRecipeTrustInfo in a recipe file on disk, keeping the
+/// rest of the file and its format as-is
+fn write_trust_info(path: &Path, trust_info: &ParentRecipeTrust) -> Result<()> {
+    const KEY: &str = "ParentRecipeTrustInfo";
+    let recipe_data = fs::read(path).map_err(|e| Error::io(path, e))?;
+    match recipe_format(path).unwrap_or(RecipeFormat::Plist) {
+        RecipeFormat::Plist => {
+            let mut dict: plist::Dictionary =
+                plist::from_bytes(&recipe_data).map_err(|e| Error::parse(path, e))?;
+            let value = plist::to_value(trust_info).map_err(|e| Error::parse(path, e))?;
+            dict.insert(KEY.to_string(), value);
+            write_recipe_data(path, &dict, RecipeFormat::Plist)
+        }
+        RecipeFormat::Yaml => {
+            let mut recipe: serde_yaml::Mapping =
+                serde_yaml::from_slice(&recipe_data).map_err(|e| Error::parse(path, e))?;
+            let value = serde_yaml::to_value(trust_info).map_err(|e| Error::parse(path, e))?;
+            recipe.insert(KEY.into(), value);
+            write_recipe_data(path, &recipe, RecipeFormat::Yaml)
+        }
+    }
+}
+
+/// Store fresh trust info for the parents of a recipe override, and return
+/// the path of the override that was updated.
+///
+/// Only recipes in the override folder, or that already have trust info,
+/// can be updated.
+pub fn update_trust_info(
+    name: &str,
+    prefs: &Preferences,
+    registry: &ProcessorRegistry,
+) -> Result<PathBuf> {
+    let recipe_map = read_recipe_map(prefs)?;
+    let recipe_path = find_recipe_in_map(&recipe_map, name)
+        .map(PathBuf::from)
+        .ok_or_else(|| Error::RecipeNotFound(name.to_string()))?;
+    let recipe = read_recipe(&recipe_path)?;
+    let invalid = |message: &str| Error::InvalidRecipe {
+        recipe: name.to_string(),
+        message: format!("{message} ({})", recipe_path.display()),
+    };
+    let Some(parent_identifier) = get_parent_identifier(&recipe) else {
+        return Err(invalid("not a recipe override and has no parent recipe"));
+    };
+    if recipe.parent_recipe_trust_info.is_none()
+        && !recipe_path.starts_with(&prefs.recipe_override_dir)
+    {
+        return Err(invalid("does not appear to be a recipe override"));
+    }
+    let parent_recipe = load_recipe(&parent_identifier, prefs)?;
+    let trust_info = get_trust_info(&parent_recipe, &recipe_map, registry)?;
+    write_trust_info(&recipe_path, &trust_info)?;
+    Ok(recipe_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipes::write_test_recipe_map;
+
+    /// Write a parent recipe that uses a custom processor, and an override
+    /// of it, and return preferences with a recipe map that finds both.
+    ///
+    /// This is only used for tests
+    fn create_test_prefs(dir: &Path) -> Preferences {
+        let recipes_dir = dir.join("recipes");
+        let overrides_dir = dir.join("overrides");
+        fs::create_dir_all(&recipes_dir).unwrap();
+        fs::create_dir_all(&overrides_dir).unwrap();
+        let parent_path = recipes_dir.join("Test.download.recipe.yaml");
+        fs::write(
+            &parent_path,
+            "Identifier: com.github.autopkg.download.Test\n\
+            Input:\n  NAME: Test\n\
+            Process:\n  - Processor: TestProcessor\n  - Processor: EndOfCheckPhase\n",
+        )
+        .unwrap();
+        fs::write(recipes_dir.join("TestProcessor.py"), "print('hello')\n").unwrap();
+        let override_path = overrides_dir.join("Test.download.recipe.yaml");
+        fs::write(
+            &override_path,
+            "Identifier: local.download.Test\n\
+            ParentRecipe: com.github.autopkg.download.Test\n\
+            Input:\n  NAME: Test\n",
+        )
+        .unwrap();
+
+        let mut prefs = Preferences::new();
+        prefs.recipe_search_dirs = vec![recipes_dir];
+        prefs.recipe_override_dir = overrides_dir;
+        prefs.recipe_repo_dir = dir.join("RecipeRepos");
+        prefs.recipe_map_path = dir.join("recipe_map.json");
+        write_test_recipe_map(
+            &prefs,
+            &[
+                (
+                    "identifiers",
+                    "com.github.autopkg.download.Test",
+                    &parent_path,
+                ),
+                ("overrides", "local.download.Test", &override_path),
+                ("overrides", "Test.download", &override_path),
+            ],
+        );
+        prefs
+    }
+
+    /// Load the test override and verify its trust info
+    fn verify_test_override(prefs: &Preferences) -> Result<()> {
+        let recipe = load_recipe("Test.download", prefs).unwrap();
+        verify_parent_trust(&recipe, prefs, &ProcessorRegistry::new(), 1)
+    }
+
+    #[test]
+    fn test_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hello.txt");
+        fs::write(&path, "hello\n").unwrap();
+        assert_eq!(
+            sha256_hash(&path).unwrap(),
+            "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+        );
+        assert_eq!(sha256_hash(dir.path()).unwrap(), NOT_A_FILE);
+        // Git hashes are only used inside a git working tree
+        assert_eq!(git_blob_hash(&path).unwrap(), None);
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        assert_eq!(
+            git_blob_hash(&path).unwrap().as_deref(),
+            Some("ce013625030ba8dba906f756967f9e9ca394464a")
+        );
+    }
+
+    #[test]
+    fn test_update_then_verify_trust_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefs = create_test_prefs(dir.path());
+        let err = verify_test_override(&prefs).unwrap_err();
+        assert!(err.to_string().contains("No trust information present."));
+
+        update_trust_info("Test.download", &prefs, &ProcessorRegistry::new()).unwrap();
+        verify_test_override(&prefs).unwrap();
+        let recipe = load_recipe("Test.download", &prefs).unwrap();
+        let trust_info = recipe.parent_recipe_trust_info.unwrap();
+        assert_eq!(
+            trust_info.non_core_processors.keys().collect::<Vec<_>>(),
+            vec!["TestProcessor"]
+        );
+        assert_eq!(
+            trust_info.parent_recipes.keys().collect::<Vec<_>>(),
+            vec!["com.github.autopkg.download.Test"]
+        );
+        // The rest of the override is untouched
+        assert_eq!(recipe.identifier, "local.download.Test");
+    }
+
+    #[test]
+    fn test_verify_trust_info_reports_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefs = create_test_prefs(dir.path());
+        update_trust_info("Test.download", &prefs, &ProcessorRegistry::new()).unwrap();
+
+        fs::write(
+            dir.path().join("recipes/TestProcessor.py"),
+            "print('goodbye')\n",
+        )
+        .unwrap();
+        let message = match verify_test_override(&prefs) {
+            Err(Error::TrustVerification { message, .. }) => message,
+            result => panic!("Expected a trust verification error, got {result:?}"),
+        };
+        assert!(message.contains("Processor TestProcessor contents differ from expected."));
+        assert!(!message.contains("Parent recipe"));
+
+        fs::remove_file(dir.path().join("recipes/TestProcessor.py")).unwrap();
+        let err = verify_test_override(&prefs).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Expected processor TestProcessor can't be found."));
+    }
+
+    #[test]
+    fn test_update_trust_info_requires_parent() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefs = create_test_prefs(dir.path());
+        let err = update_trust_info(
+            "com.github.autopkg.download.Test",
+            &prefs,
+            &ProcessorRegistry::new(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::InvalidRecipe { .. }));
+    }
+}
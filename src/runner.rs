@@ -0,0 +1,743 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+use tracing::{debug, error, error_span, field, info, warn, Span};
+
+use crate::constants::AUTOPKG_VERSION;
+use crate::error::{Error, Result};
+use crate::processors::{Environment, ProcessorRegistry};
+use crate::recipes::substitution::update_data;
+use crate::recipes::trust::verify_parent_trust;
+use crate::recipes::{self, version_equal_or_greater, PlistDataType, Recipe, RecipeFormat};
+use crate::Preferences;
+
+/// Options that apply to every recipe in a run
+#[derive(Debug, Default)]
+pub struct RunOptions {
+    /// Only run the processors up to the last EndOfCheckPhase
+    pub check_only: bool,
+    /// Run recipes even if they fail parent trust verification
+    pub ignore_trust_verification_errors: bool,
+    /// Processors to run before each recipe
+    pub preprocessors: Vec<String>,
+    /// Processors to run after each recipe
+    pub postprocessors: Vec<String>,
+    /// Values that override the recipe Input, from -k KEY=VALUE or a recipe
+    /// list
+    pub cli_values: Environment,
+}
+
+/// What happened when a recipe was run
+#[derive(Debug)]
+pub enum RecipeOutcome {
+    /// Every processor ran, and nothing new was downloaded
+    Success,
+    /// Every processor ran, and a new download was found
+    NewDownload,
+    /// A processor asked for the recipe to stop early
+    Stopped,
+    /// The recipe couldn't be loaded, or one of its processors failed
+    Failed(Error),
+}
+
+/// The result of running a single recipe
+#[derive(Debug)]
+pub struct RecipeResult {
+    /// The recipe name as it was given to run
+    pub recipe: String,
+    pub outcome: RecipeOutcome,
+    /// Names of the processors that ran, in order
+    pub processors: Vec<String>,
+    /// The environment after the last processor ran
+    pub env: Environment,
+}
+
+/// A list of recipes to run, along with any values that apply to all of them
+#[derive(Debug, Default)]
+pub struct RecipeList {
+    pub recipes: Vec<String>,
+    pub preprocessors: Vec<String>,
+    pub postprocessors: Vec<String>,
+    pub values: Environment,
+}
+
+/// Parse a recipe list file.
+///
+/// Like Python AutoPkg, this can be either a plist dictionary with a
+/// "recipes" array (plus optional "preprocessors", "postprocessors" and
+/// any other keys to use as input values), or a plain text file with one
+/// recipe per line. Blank lines and lines starting with # are ignored.
+pub fn parse_recipe_list(path: &Path) -> Result<RecipeList> {
+    if let Ok(plist::Value::Dictionary(dict)) = plist::Value::from_file(path) {
+        let mut recipe_list = RecipeList::default();
+        let strings = |value: &plist::Value| -> Vec<String> {
+            value
+                .as_array()
+                .map(|array| {
+                    array
+                        .iter()
+                        .filter_map(|item| item.as_string().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        for (key, value) in dict {
+            match key.as_str() {
+                "recipes" => recipe_list.recipes = strings(&value),
+                "preprocessors" => recipe_list.preprocessors = strings(&value),
+                "postprocessors" => recipe_list.postprocessors = strings(&value),
+                _ => {
+                    recipe_list.values.insert(key, PlistDataType::from(value));
+                }
+            }
+        }
+        return Ok(recipe_list);
+    }
+    // The file doesn't look like a plist dictionary, so read it as text
+    let text = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    Ok(RecipeList {
+        recipes: text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect(),
+        ..Default::default()
+    })
+}
+
+/// Whether recipes without any trust info should fail instead of running
+/// with a warning, from the FAIL_RECIPES_WITHOUT_TRUST_INFO input value or
+/// preference
+fn fail_recipes_without_trust_info(prefs: &Preferences, options: &RunOptions) -> bool {
+    const KEY: &str = "FAIL_RECIPES_WITHOUT_TRUST_INFO";
+    let value = options
+        .cli_values
+        .get(KEY)
+        .or_else(|| prefs.extras.as_ref().and_then(|extras| extras.get(KEY)));
+    match value {
+        Some(PlistDataType::Bool(value)) => *value,
+        Some(PlistDataType::Str(value)) => !value.is_empty(),
+        Some(_) => true,
+        None => false,
+    }
+}
+
+/// Add the pre- and postprocessors to the beginning and end of the Process
+fn add_pre_and_postprocessors(recipe: &mut Recipe, options: &RunOptions) {
+    let step = |name: &String| recipes::Processor {
+        processor: name.to_owned(),
+        arguments: None,
+    };
+    let preprocessors = options.preprocessors.iter().map(step);
+    recipe.process.splice(0..0, preprocessors);
+    recipe
+        .process
+        .extend(options.postprocessors.iter().map(step));
+}
+
+/// Remove every step after the last EndOfCheckPhase, for check-only runs
+fn truncate_to_check_phase(recipe: &mut Recipe) -> Result<()> {
+    let Some(end) = recipe
+        .process
+        .iter()
+        .rposition(|step| step.processor == "EndOfCheckPhase")
+    else {
+        return Err(Error::InvalidRecipe {
+            recipe: recipe.identifier.to_owned(),
+            message: "missing EndOfCheckPhase Processor, not possible to perform check".to_string(),
+        });
+    };
+    recipe.process.truncate(end + 1);
+    Ok(())
+}
+
+/// Build the environment a recipe runs in.
+///
+/// Later sources win over earlier ones: preferences first, then the recipe
+/// Input, then values from the command line. Once everything is merged, every
+/// value has its %NAME% references substituted, in that same order, with the
+/// Input in the order it's in the recipe. Like Python AutoPkg, each value is
+/// only substituted once, so a value only sees the substituted form of the
+/// values before it.
+fn build_environment(
+    recipe: &Recipe,
+    prefs: &Preferences,
+    options: &RunOptions,
+    recipe_cache_dir: &Path,
+) -> Environment {
+    let mut env = prefs.to_environment();
+    env.insert(
+        "AUTOPKG_VERSION".to_string(),
+        PlistDataType::Str(AUTOPKG_VERSION.to_string()),
+    );
+    if let Some(recipe_path) = &recipe.recipe_path {
+        env.insert(
+            "RECIPE_PATH".to_string(),
+            PlistDataType::Str(recipe_path.display().to_string()),
+        );
+        if let Some(recipe_dir) = recipe_path.parent() {
+            env.insert(
+                "RECIPE_DIR".to_string(),
+                PlistDataType::Str(recipe_dir.display().to_string()),
+            );
+        }
+    }
+    env.insert(
+        "PARENT_RECIPES".to_string(),
+        PlistDataType::from(
+            recipe
+                .parent_recipes
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<String>>(),
+        ),
+    );
+    env.insert(
+        "RECIPE_CACHE_DIR".to_string(),
+        PlistDataType::Str(recipe_cache_dir.display().to_string()),
+    );
+
+    // Preferences are sorted, since they don't have an order of their own
+    let mut keys: Vec<String> = env.keys().cloned().collect();
+    keys.sort();
+    let mut cli_keys: Vec<&String> = options.cli_values.keys().collect();
+    cli_keys.sort();
+    let cli_values = cli_keys
+        .into_iter()
+        .map(|key| (key, &options.cli_values[key]));
+    for (key, value) in recipe.input.iter().chain(cli_values) {
+        if env.insert(key.to_owned(), value.clone()).is_none() {
+            keys.push(key.to_owned());
+        }
+    }
+    for key in keys {
+        let value = env[&key].clone();
+        update_data(&mut env, &key, &value);
+    }
+    env
+}
+
+/// Verify a recipe can run before running any of it.
+///
+/// This makes sure AutoPkg is new enough, that every processor exists, and
+/// that every required input variable will have been set by the time its
+/// processor runs.
+fn verify(recipe: &Recipe, env: &Environment, registry: &ProcessorRegistry) -> Result<()> {
+    if !recipe.minimum_version.is_empty()
+        && !version_equal_or_greater(AUTOPKG_VERSION, &recipe.minimum_version)
+    {
+        return Err(Error::InvalidRecipe {
+            recipe: recipe.identifier.to_owned(),
+            message: format!(
+                "requires at least AutoPkg version {}, but this is version {AUTOPKG_VERSION}",
+                recipe.minimum_version
+            ),
+        });
+    }
+    let mut variables: HashSet<&str> = env.keys().map(String::as_str).collect();
+    for step in &recipe.process {
+        let processor = registry.get(&step.processor)?;
+        if let Some(arguments) = &step.arguments {
+            variables.extend(arguments.keys().map(String::as_str));
+        }
+        for input in processor.input_variables() {
+            if input.required && !variables.contains(input.name) {
+                return Err(Error::Processor {
+                    processor: step.processor.to_owned(),
+                    message: format!("requires missing argument {}", input.name),
+                });
+            }
+        }
+        variables.extend(
+            processor
+                .output_variables()
+                .iter()
+                .map(|output| output.name),
+        );
+    }
+    Ok(())
+}
+
+/// Load, verify and run every processor in a recipe, recording progress in
+/// the result as we go
+fn process_recipe(
+    name: &str,
+    prefs: &Preferences,
+    registry: &ProcessorRegistry,
+    options: &RunOptions,
+    result: &mut RecipeResult,
+) -> Result<()> {
+    let mut recipe = recipes::load_recipe(name, prefs)?;
+    Span::current().record("identifier", recipe.identifier.as_str());
+    if recipe.parent_recipe_trust_info.is_none() && !fail_recipes_without_trust_info(prefs, options)
+    {
+        warn!(
+            "{name} is missing trust info and FAIL_RECIPES_WITHOUT_TRUST_INFO is not set. Proceeding..."
+        );
+    } else if let Err(e) = verify_parent_trust(&recipe, prefs, registry, 0) {
+        // Trust is always checked, so a failure is reported even when it's
+        // ignored
+        if !options.ignore_trust_verification_errors {
+            return Err(e);
+        }
+        warn!("{e}");
+        warn!("Continuing anyway, since parent trust verification errors are ignored");
+    }
+    add_pre_and_postprocessors(&mut recipe, options);
+    if options.check_only {
+        truncate_to_check_phase(&mut recipe)?;
+    }
+    // Every recipe gets its own cache directory to work in, which the Input
+    // can refer to
+    let recipe_cache_dir = prefs.cache_dir.join(&recipe.identifier);
+    result.env = build_environment(&recipe, prefs, options, &recipe_cache_dir);
+    verify(&recipe, &result.env, registry)?;
+    fs::create_dir_all(&recipe_cache_dir).map_err(|e| Error::io(&recipe_cache_dir, e))?;
+
+    for step in &recipe.process {
+        let _span = error_span!("processor", processor = %step.processor).entered();
+        info!("{}", step.processor);
+        let processor = registry.get(&step.processor)?;
+        if let Some(arguments) = &step.arguments {
+            for (key, value) in arguments {
+                update_data(&mut result.env, key, value);
+            }
+        }
+        processor.process(&mut result.env)?;
+        result.processors.push(step.processor.to_owned());
+        if result.env.get("stop_processing_recipe") == Some(&PlistDataType::Bool(true)) {
+            debug!("{} asked to stop processing", step.processor);
+            result.outcome = RecipeOutcome::Stopped;
+            return Ok(());
+        }
+    }
+    if result.env.get("download_changed") == Some(&PlistDataType::Bool(true)) {
+        result.outcome = RecipeOutcome::NewDownload;
+    }
+    Ok(())
+}
+
+/// Run a single recipe by identifier or shortname.
+///
+/// This never returns an error; failures are recorded in the result instead,
+/// so that one bad recipe doesn't stop the rest of a run.
+pub fn run_recipe(
+    name: &str,
+    prefs: &Preferences,
+    registry: &ProcessorRegistry,
+    options: &RunOptions,
+) -> RecipeResult {
+    // Log messages from the run carry the recipe they're for, and its
+    // identifier once it's been loaded. The span is at the error level so
+    // it's never filtered out, whatever level messages are logged at
+    let _span = error_span!("recipe", recipe = name, identifier = field::Empty).entered();
+    info!("Processing {name}...");
+    let mut result = RecipeResult {
+        recipe: name.to_string(),
+        outcome: RecipeOutcome::Success,
+        processors: Vec::new(),
+        env: Environment::new(),
+    };
+    if let Err(e) = process_recipe(name, prefs, registry, options, &mut result) {
+        error!("Failed {name}: {e}");
+        result.outcome = RecipeOutcome::Failed(e);
+    }
+    result
+}
+
+/// Run each recipe independently, continuing past any failures
+pub fn run_recipes(
+    names: &[String],
+    prefs: &Preferences,
+    registry: &ProcessorRegistry,
+    options: &RunOptions,
+) -> Vec<RecipeResult> {
+    names
+        .iter()
+        .map(|name| run_recipe(name, prefs, registry, options))
+        .collect()
+}
+
+/// A recipe that failed, as written in the run report
+#[derive(Debug, Serialize)]
+pub struct ReportFailure {
+    pub message: String,
+    pub recipe: String,
+}
+
+/// Every row a processor added under one summary result name, such as
+/// `deprecation_summary_result`
+#[derive(Debug, Serialize)]
+pub struct SummaryResult {
+    pub summary_text: String,
+    pub report_fields: Vec<String>,
+    pub data_rows: Vec<PlistDataType>,
+}
+
+/// The report Python AutoPkg writes with --report-plist
+#[derive(Debug, Default, Serialize)]
+pub struct RunReport {
+    pub failures: Vec<ReportFailure>,
+    pub summary_results: BTreeMap<String, SummaryResult>,
+}
+
+impl RunReport {
+    /// Build the report for a run: every recipe that failed, and the summary
+    /// results processors left in the environment, gathered by result name
+    pub fn new(results: &[RecipeResult]) -> RunReport {
+        let mut report = RunReport::default();
+        for result in results {
+            if let RecipeOutcome::Failed(e) = &result.outcome {
+                report.failures.push(ReportFailure {
+                    message: e.to_string(),
+                    recipe: result.recipe.to_owned(),
+                });
+            }
+            let mut summaries: Vec<_> = result
+                .env
+                .iter()
+                .filter(|(key, _)| key.ends_with("_summary_result"))
+                .filter_map(|(key, value)| Some((key, value.as_dict()?)))
+                .collect();
+            summaries.sort_by_key(|(key, _)| *key);
+            for (key, summary) in summaries {
+                let Some(data) = summary.get("data") else {
+                    continue;
+                };
+                report
+                    .summary_results
+                    .entry(key.to_owned())
+                    .or_insert_with(|| SummaryResult {
+                        summary_text: summary
+                            .get("summary_text")
+                            .and_then(PlistDataType::as_str)
+                            .unwrap_or_default()
+                            .to_string(),
+                        report_fields: summary
+                            .get("report_fields")
+                            .and_then(PlistDataType::as_array)
+                            .unwrap_or_default()
+                            .iter()
+                            .filter_map(|field| field.as_str().map(str::to_string))
+                            .collect(),
+                        data_rows: Vec::new(),
+                    })
+                    .data_rows
+                    .push(data.clone());
+            }
+        }
+        report
+    }
+}
+
+/// Write the report for a run to a plist file
+pub fn write_report_plist(path: &Path, results: &[RecipeResult]) -> Result<()> {
+    recipes::write_recipe_data(path, &RunReport::new(results), RecipeFormat::Plist)
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+
+    use super::*;
+    use crate::recipes::write_test_recipe_map;
+
+    /// Write a yaml recipe that creates a file after its check phase, and a
+    /// recipe map pointing to it, and return preferences that use them.
+    ///
+    /// This is only used for tests
+    fn create_test_prefs(dir: &Path) -> Preferences {
+        let recipe_yaml = "\
+Description: Creates a file
+Identifier: com.github.autopkg.test.FileCreator
+MinimumVersion: '2.3'
+Input:
+  NAME: Test
+  FILE_PATH: placeholder
+Process:
+  - Processor: EndOfCheckPhase
+  - Processor: FileCreator
+    Arguments:
+      file_path: '%FILE_PATH%'
+      file_content: '%NAME% was here'
+";
+        let recipe_path = dir.join("Test.create.recipe.yaml");
+        fs::write(&recipe_path, recipe_yaml).unwrap();
+        let broken_path = dir.join("Broken.create.recipe.yaml");
+        fs::write(&broken_path, "not: [a recipe").unwrap();
+
+        let mut prefs = Preferences::new();
+        prefs.recipe_map_path = dir.join("recipe_map.json");
+        prefs.cache_dir = dir.join("Cache");
+        write_test_recipe_map(
+            &prefs,
+            &[
+                (
+                    "identifiers",
+                    "com.github.autopkg.test.FileCreator",
+                    &recipe_path,
+                ),
+                ("shortnames", "Test.create", &recipe_path),
+                ("shortnames", "Broken.create", &broken_path),
+            ],
+        );
+        prefs
+    }
+
+    /// Run options that point the recipe at a file in the test folder
+    fn create_test_options(dir: &Path) -> RunOptions {
+        RunOptions {
+            cli_values: Environment::from([(
+                "FILE_PATH".to_string(),
+                PlistDataType::Str(dir.join("created.txt").display().to_string()),
+            )]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_run_recipe_substitutes_and_runs_processors() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefs = create_test_prefs(dir.path());
+        let options = create_test_options(dir.path());
+        let result = run_recipe("Test.create", &prefs, &ProcessorRegistry::new(), &options);
+        assert!(matches!(result.outcome, RecipeOutcome::Success));
+        assert_eq!(result.processors, vec!["EndOfCheckPhase", "FileCreator"]);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("created.txt")).unwrap(),
+            "Test was here"
+        );
+        assert!(dir
+            .path()
+            .join("Cache/com.github.autopkg.test.FileCreator")
+            .is_dir());
+    }
+
+    #[test]
+    fn test_build_environment_substitutes_in_order() {
+        let mut recipe = Recipe::new(
+            "Chained references".to_string(),
+            "com.github.autopkg.test.Chained".to_string(),
+            "2.3".to_string(),
+            None,
+        );
+        let string = |value: &str| PlistDataType::Str(value.to_string());
+        recipe.input = IndexMap::from([
+            ("B".to_string(), string("%C%")),
+            ("A".to_string(), string("%B%/x")),
+            ("LATE".to_string(), string("%C%/%LATER%")),
+            ("LATER".to_string(), string("%C%")),
+            ("C".to_string(), string("c")),
+            ("CACHED".to_string(), string("%RECIPE_CACHE_DIR%/file")),
+        ]);
+        let options = RunOptions {
+            cli_values: Environment::from([
+                ("C".to_string(), string("cli")),
+                ("FROM_CLI".to_string(), string("%A%!")),
+            ]),
+            ..Default::default()
+        };
+        let env = build_environment(
+            &recipe,
+            &Preferences::new(),
+            &options,
+            Path::new("/Cache/Chained"),
+        );
+        // Each value sees the substituted form of the values before it, and
+        // the raw form of the values after it
+        assert_eq!(env["B"], string("cli"));
+        assert_eq!(env["A"], string("cli/x"));
+        assert_eq!(env["LATE"], string("cli/%C%"));
+        assert_eq!(env["LATER"], string("cli"));
+        assert_eq!(env["FROM_CLI"], string("cli/x!"));
+        assert_eq!(env["CACHED"], string("/Cache/Chained/file"));
+    }
+
+    /// Run something and return every warning it logs, as JSON events, the
+    /// same way they're logged by default
+    fn capture_warnings(run: impl FnOnce()) -> Vec<serde_json::Value> {
+        use std::io;
+        use std::sync::{Arc, Mutex};
+        use tracing_subscriber::filter::LevelFilter;
+        use tracing_subscriber::prelude::*;
+
+        /// Collects everything that's logged, so it can be checked
+        #[derive(Clone, Default)]
+        struct Captured(Arc<Mutex<Vec<u8>>>);
+        impl io::Write for Captured {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let captured = Captured::default();
+        let writer = captured.clone();
+        // Only warnings and errors are logged by default
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_span_list(true)
+                .with_writer(move || writer.clone())
+                .with_filter(LevelFilter::WARN),
+        );
+        tracing::subscriber::with_default(subscriber, run);
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .filter(|event: &serde_json::Value| event["level"] == "WARN")
+            .collect()
+    }
+
+    #[test]
+    fn test_warnings_carry_the_recipe_span() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefs = create_test_prefs(dir.path());
+        let options = create_test_options(dir.path());
+        let warnings = capture_warnings(|| {
+            run_recipe("Test.create", &prefs, &ProcessorRegistry::new(), &options);
+        });
+        // The recipe has no trust info, which is a warning
+        let warning = &warnings[0];
+        assert_eq!(warning["span"]["recipe"], "Test.create");
+        assert_eq!(
+            warning["span"]["identifier"],
+            "com.github.autopkg.test.FileCreator"
+        );
+    }
+
+    #[test]
+    fn test_run_recipe_check_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefs = create_test_prefs(dir.path());
+        let options = RunOptions {
+            check_only: true,
+            ..create_test_options(dir.path())
+        };
+        let result = run_recipe("Test.create", &prefs, &ProcessorRegistry::new(), &options);
+        assert!(matches!(result.outcome, RecipeOutcome::Success));
+        assert_eq!(result.processors, vec!["EndOfCheckPhase"]);
+        assert!(!dir.path().join("created.txt").exists());
+    }
+
+    #[test]
+    fn test_run_recipes_continues_past_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefs = create_test_prefs(dir.path());
+        let options = create_test_options(dir.path());
+        let names = vec![
+            "Broken.create".to_string(),
+            "Missing.create".to_string(),
+            "Test.create".to_string(),
+        ];
+        let results = run_recipes(&names, &prefs, &ProcessorRegistry::new(), &options);
+        assert!(matches!(
+            results[0].outcome,
+            RecipeOutcome::Failed(Error::Parse { .. })
+        ));
+        assert!(matches!(
+            results[1].outcome,
+            RecipeOutcome::Failed(Error::RecipeNotFound(_))
+        ));
+        assert!(matches!(results[2].outcome, RecipeOutcome::Success));
+    }
+
+    #[test]
+    fn test_verify_missing_required_argument() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefs = create_test_prefs(dir.path());
+        let options = RunOptions {
+            postprocessors: vec!["PathDeleter".to_string()],
+            ..create_test_options(dir.path())
+        };
+        let result = run_recipe("Test.create", &prefs, &ProcessorRegistry::new(), &options);
+        // Verification happens before anything runs
+        assert!(matches!(
+            result.outcome,
+            RecipeOutcome::Failed(Error::Processor { .. })
+        ));
+        assert!(result.processors.is_empty());
+    }
+
+    #[test]
+    fn test_run_recipe_requires_trust_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefs = create_test_prefs(dir.path());
+        let mut options = create_test_options(dir.path());
+        options.cli_values.insert(
+            "FAIL_RECIPES_WITHOUT_TRUST_INFO".to_string(),
+            PlistDataType::Bool(true),
+        );
+        let registry = ProcessorRegistry::new();
+        let result = run_recipe("Test.create", &prefs, &registry, &options);
+        assert!(matches!(
+            result.outcome,
+            RecipeOutcome::Failed(Error::TrustVerification { .. })
+        ));
+
+        // Trust is still checked when failures are ignored, and the failure
+        // is logged as a warning
+        options.ignore_trust_verification_errors = true;
+        let mut result = None;
+        let warnings = capture_warnings(|| {
+            result = Some(run_recipe("Test.create", &prefs, &registry, &options));
+        });
+        assert!(matches!(result.unwrap().outcome, RecipeOutcome::Success));
+        assert!(warnings.iter().any(|warning| warning["fields"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("failed trust verification")));
+    }
+
+    #[test]
+    fn test_write_report_plist() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefs = create_test_prefs(dir.path());
+        let options = RunOptions {
+            postprocessors: vec!["DeprecationWarning".to_string()],
+            ..create_test_options(dir.path())
+        };
+        let names = vec!["Missing.create".to_string(), "Test.create".to_string()];
+        let results = run_recipes(&names, &prefs, &ProcessorRegistry::new(), &options);
+        let report_path = dir.path().join("report.plist");
+        write_report_plist(&report_path, &results).unwrap();
+
+        let report = plist::Value::from_file(&report_path).unwrap();
+        let report = report.as_dictionary().unwrap();
+        let failures = report["failures"].as_array().unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(
+            failures[0].as_dictionary().unwrap()["recipe"].as_string(),
+            Some("Missing.create")
+        );
+        let summary = report["summary_results"].as_dictionary().unwrap()
+            ["deprecation_summary_result"]
+            .as_dictionary()
+            .unwrap();
+        assert_eq!(summary["data_rows"].as_array().unwrap().len(), 1);
+        assert_eq!(summary["report_fields"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_parse_recipe_list_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let list_path = dir.path().join("recipes.txt");
+        fs::write(
+            &list_path,
+            "# Browsers\nFirefox.munki\n\nGoogleChrome.munki\n",
+        )
+        .unwrap();
+        let recipe_list = parse_recipe_list(&list_path).unwrap();
+        assert_eq!(
+            recipe_list.recipes,
+            vec!["Firefox.munki", "GoogleChrome.munki"]
+        );
+        assert!(recipe_list.values.is_empty());
+    }
+}